@@ -0,0 +1,169 @@
+use parser::ast::Span;
+use std::fmt;
+
+/// A span of a source file to point at, with a note about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label<'a> {
+  /// Name of the file to show, e.g. `part.oc` or `<stdin>`
+  pub file: &'a str,
+  pub source: &'a str,
+  pub span: Span,
+  pub msg: String,
+}
+
+/// A message about a program, rendered the way rustc renders its errors:
+///
+/// ```text
+/// error: Couldn't find identifier: x
+///  --> part.oc:3:7
+///   |
+/// 3 | print(x);
+///   |       ^
+/// ```
+///
+/// The primary label is underlined with `^`; secondary labels, such as
+/// "function defined here", are underlined with `-`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic<'a> {
+  pub severity: &'a str,
+  pub msg: String,
+  pub primary: Option<Label<'a>>,
+  pub secondary: Vec<Label<'a>>,
+}
+
+/// Where a label's span starts, along with the source line it's on and how
+/// many characters of that line to underline
+struct Located<'a> {
+  line: usize,
+  col: usize,
+  text: &'a str,
+  width: usize,
+}
+
+/// Works out the line and column of a span. A span may start at the very
+/// end of the source, for errors about a missing token. Spans covering
+/// several lines are only underlined up to the end of the first.
+fn locate<'a>(source: &'a str, span: Span) -> Located<'a> {
+  let mut start = span.start.min(source.len());
+  while !source.is_char_boundary(start) {
+    start -= 1;
+  }
+  let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+  let line_end = source[start..]
+    .find('\n')
+    .map_or(source.len(), |i| start + i);
+  let mut end = span.end.max(start).min(line_end);
+  while !source.is_char_boundary(end) {
+    end -= 1;
+  }
+  Located {
+    line: source[..start].matches('\n').count() + 1,
+    col: source[line_start..start].chars().count() + 1,
+    text: &source[line_start..line_end],
+    width: source[start..end].chars().count().max(1),
+  }
+}
+
+impl<'a> fmt::Display for Diagnostic<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}: {}", self.severity, self.msg)?;
+    let labels: Vec<(&Label, char)> = self
+      .primary
+      .iter()
+      .map(|label| (label, '^'))
+      .chain(self.secondary.iter().map(|label| (label, '-')))
+      .collect();
+    let located: Vec<Located> = labels
+      .iter()
+      .map(|(label, _)| locate(label.source, label.span))
+      .collect();
+    let gutter = located
+      .iter()
+      .map(|loc| loc.line.to_string().len())
+      .max()
+      .unwrap_or(0);
+
+    let mut previous_file = None;
+    for ((label, marker), loc) in labels.iter().zip(located.iter()) {
+      if previous_file != Some(label.file) {
+        let arrow = if previous_file.is_none() {
+          "-->"
+        } else {
+          ":::"
+        };
+        write!(
+          f,
+          "\n{:w$}{} {}:{}:{}\n{:w$} |",
+          "",
+          arrow,
+          label.file,
+          loc.line,
+          loc.col,
+          "",
+          w = gutter
+        )?;
+        previous_file = Some(label.file);
+      }
+      // tabs would throw off the underline
+      let text = loc.text.replace('\t', " ");
+      write!(f, "\n{:>w$} | {}", loc.line, text, w = gutter)?;
+      let underline: String = std::iter::repeat(*marker).take(loc.width).collect();
+      write!(
+        f,
+        "\n{:w$} | {:pad$}{}",
+        "",
+        "",
+        underline,
+        w = gutter,
+        pad = loc.col - 1
+      )?;
+      if !label.msg.is_empty() {
+        write!(f, " {}", label.msg)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_render() {
+    let source = "fn f(a) {\n  return a;\n}\nx = «f»(1, 2);";
+    let call = source.find("«f»").unwrap();
+    let diagnostic = Diagnostic {
+      severity: "error",
+      msg: "Too many arguments".to_string(),
+      primary: Some(Label {
+        file: "part.oc",
+        source: source,
+        span: Span {
+          start: call,
+          end: source.len() - 1,
+        },
+        msg: "called with 2 arguments".to_string(),
+      }),
+      secondary: vec![Label {
+        file: "part.oc",
+        source: source,
+        span: Span {
+          start: 0,
+          end: source.len(),
+        },
+        msg: "function defined here".to_string(),
+      }],
+    };
+    let expected = [
+      "error: Too many arguments",
+      " --> part.oc:4:5",
+      "  |",
+      "4 | x = «f»(1, 2);",
+      "  |     ^^^^^^^^^ called with 2 arguments",
+      "1 | fn f(a) {",
+      "  | --------- function defined here",
+    ];
+    assert_eq!(diagnostic.to_string(), expected.join("\n"));
+  }
+}
@@ -3,9 +3,11 @@ use solid::*;
 use std::io::prelude::*;
 use std::io::Result;
 
-pub fn write_stl<W: Write>(write: &mut W, solid: Solid, name: &str) -> Result<()> {
+/// Writes a solid as ASCII STL, returning the number of triangles written
+pub fn write_stl<W: Write>(write: &mut W, solid: Solid, name: &str) -> Result<usize> {
   let tris = triangulate_solid(solid);
-  writeln!(write, "solid {}", name);
+  let count = tris.len();
+  writeln!(write, "solid {}", name)?;
   for tri in tris {
     let normal: Unit = (tri[1] - tri[0]).cross(&(tri[2] - tri[0])).into();
     writeln!(
@@ -25,5 +27,5 @@ pub fn write_stl<W: Write>(write: &mut W, solid: Solid, name: &str) -> Result<()
     writeln!(write, "endfacet")?;
   }
   writeln!(write, "endsolid {}", name)?;
-  Ok(())
+  Ok(count)
 }
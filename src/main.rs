@@ -14,22 +14,29 @@ extern crate kiss3d;
 extern crate nalgebra;
 
 mod boolean;
+mod diagnostic;
 #[cfg(feature = "display")]
 mod display;
 mod format;
 mod ops;
+mod output;
 mod parser;
 mod runtime;
 mod solid;
 mod stdlib;
+mod typecheck;
+mod units;
 
 use ops::*;
 use solid::*;
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg};
 use std::fs::File;
 use std::io;
 use std::io::BufRead;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 
 fn test_boolean() {
   let outside_box = Solid::make_box([2.0, 2.0, 2.0]);
@@ -61,21 +68,200 @@ fn test_boolean() {
   }
 }
 
+fn is_number(value: String) -> Result<(), String> {
+  value
+    .parse::<u64>()
+    .map(|_| ())
+    .map_err(|_| format!("expected a whole number, got `{}`", value))
+}
+
+fn is_nesting_limit(value: String) -> Result<(), String> {
+  match value.parse::<usize>() {
+    Ok(n) if n <= runtime::MAX_NESTING_LIMIT => Ok(()),
+    _ => Err(format!(
+      "expected a whole number no bigger than {}, got `{}`",
+      runtime::MAX_NESTING_LIMIT,
+      value
+    )),
+  }
+}
+
 fn main() {
   // #[cfg(feature = "display")]
   // test_boolean();
-  let stdin = io::stdin();
-  let program_string = stdin
-    .lock()
-    .lines()
-    .filter_map(|l| l.ok())
-    .collect::<Vec<_>>()
-    .join("\n");
+  let matches = App::new("ourCAD")
+    .arg(
+      Arg::with_name("FILE")
+        .help("Program to run; read from stdin if not given")
+        .index(1),
+    )
+    .arg(
+      Arg::with_name("lib")
+        .short("L")
+        .long("lib")
+        .value_name("DIR")
+        .help("Adds a directory to search for imported modules")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1),
+    )
+    .arg(
+      Arg::with_name("max-depth")
+        .long("max-depth")
+        .value_name("N")
+        .help("Stops the program if more than N function calls are in progress [default: 100]")
+        .validator(is_number),
+    )
+    .arg(
+      Arg::with_name("max-nesting")
+        .long("max-nesting")
+        .value_name("N")
+        .help("Stops the program if statements, expressions and calls are nested more than N deep [default: 1000]")
+        .validator(is_nesting_limit),
+    )
+    .arg(
+      Arg::with_name("max-steps")
+        .long("max-steps")
+        .value_name("N")
+        .help("Stops the program after it takes N steps; each statement, expression and range element is a step")
+        .validator(is_number),
+    )
+    .arg(
+      Arg::with_name("timeout")
+        .long("timeout")
+        .value_name("SECONDS")
+        .help("Stops the program after it runs for SECONDS")
+        .validator(is_number),
+    )
+    .arg(
+      Arg::with_name("out")
+        .short("o")
+        .long("out")
+        .value_name("DIR")
+        .help("Directory that exported files are written to [default: .]")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("dry-run")
+        .long("dry-run")
+        .help("Lists the files the program would export without writing them"),
+    )
+    .get_matches();
+
+  let program_string = match matches.value_of("FILE") {
+    Some(file) => match std::fs::read_to_string(file) {
+      Ok(program_string) => program_string,
+      Err(e) => {
+        eprintln!("error: Couldn't read {}: {}", file, e);
+        std::process::exit(1);
+      }
+    },
+    None => {
+      let stdin = io::stdin();
+      let lines = stdin
+        .lock()
+        .lines()
+        .filter_map(|l| l.ok())
+        .collect::<Vec<_>>();
+      lines.join("\n")
+    }
+  };
+
+  // errors name the file the same way whether they're found before or
+  // while the program runs, which is by its canonical path
+  let file = matches.value_of("FILE").map(|file| {
+    let path = PathBuf::from(file);
+    path.canonicalize().unwrap_or(path)
+  });
+  let file_name = file
+    .as_ref()
+    .map_or("<stdin>".to_string(), |file| file.display().to_string());
+  let number = |name| matches.value_of(name).map(|n| n.parse::<u64>().unwrap());
+  let defaults = runtime::Limits::default();
+  let limits = runtime::Limits {
+    max_call_depth: number("max-depth")
+      .map(|n| n as usize)
+      .or(defaults.max_call_depth),
+    max_nesting: number("max-nesting")
+      .map(|n| n as usize)
+      .or(defaults.max_nesting),
+    max_steps: number("max-steps"),
+    max_time: number("timeout").map(Duration::from_secs),
+  };
+  let libs: Vec<PathBuf> = matches
+    .values_of("lib")
+    .into_iter()
+    .flatten()
+    .map(PathBuf::from)
+    .collect();
+  let policy = output::OutputPolicy {
+    root: PathBuf::from(matches.value_of("out").unwrap_or(".")),
+    dry_run: matches.is_present("dry-run"),
+  };
 
-  if let Some(ast) = parser::parse::parse_program(&program_string) {
-    runtime::Runtime::new(program_string, None)
-      .run(&ast)
-      .unwrap();
+  // the nesting limit only keeps the stack from overflowing if the stack is
+  // as big as the limit needs. Parsing and checking walk the program as
+  // deeply as running it does, so they happen on the same thread. Failures
+  // exit straight away, as dropping a program that was too deeply nested
+  // to check would overflow the stack too.
+  let mut thread = thread::Builder::new();
+  if let Some(size) = limits.stack_size() {
+    thread = thread.stack_size(size);
+  }
+  let spawned = thread.spawn(move || {
+    let ast = match parser::parse::parse_program(&program_string) {
+      Ok(ast) => ast,
+      Err(errors) => {
+        for error in &errors {
+          println!("{}\n", error.render(&file_name, &program_string));
+        }
+        std::process::exit(1);
+      }
+    };
+    let type_errors = typecheck::check_program(&ast, 0, limits.max_nesting);
+    for error in &type_errors {
+      println!("{}\n", error.render(&file_name, &program_string));
+    }
+    if !type_errors.is_empty() {
+      std::process::exit(1);
+    }
+
+    let mut runtime = runtime::Runtime::new(program_string, None);
+    if let Some(file) = file {
+      runtime.set_file(file);
+    }
+    for dir in libs {
+      runtime.add_library_path(dir);
+    }
+    runtime.set_limits(limits);
+    let dry_run = policy.dry_run;
+    runtime.set_output_policy(policy);
+    let result = runtime.run(&ast);
+    // files written before a failure are listed too
+    for artifact in runtime.artifacts() {
+      eprintln!(
+        "{} {} ({}, {} triangles)",
+        if dry_run { "Would write" } else { "Wrote" },
+        artifact.path.display(),
+        artifact.format,
+        artifact.triangles
+      );
+    }
+    if let Err(e) = result {
+      println!("{}", e.render());
+      std::process::exit(1);
+    }
+  });
+  let finished = match spawned {
+    Ok(handle) => handle.join(),
+    Err(e) => {
+      eprintln!("error: Couldn't start the program: {}", e);
+      std::process::exit(1);
+    }
+  };
+  if finished.is_err() {
+    // the panic has already been reported
+    std::process::exit(101);
   }
   // println!("{:?}", Solid::make_box([2.0, 2.0, 2.0]));
 }
@@ -0,0 +1,183 @@
+use runtime::{ErrorKind, RuntimeError};
+use std::fs;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Where programs may write files. Programs name files relative to the
+/// output root and can't reach outside of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputPolicy {
+  pub root: PathBuf,
+  /// Records what would be written without touching the filesystem
+  pub dry_run: bool,
+}
+
+impl Default for OutputPolicy {
+  fn default() -> OutputPolicy {
+    OutputPolicy {
+      root: PathBuf::from("."),
+      dry_run: false,
+    }
+  }
+}
+
+/// A file exported by a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+  /// Where the file was written, including the output root
+  pub path: PathBuf,
+  /// File format, e.g. `stl`
+  pub format: &'static str,
+  pub triangles: usize,
+}
+
+/// Exports files on behalf of a program, following an output policy and
+/// keeping track of what was written
+pub struct Outputs {
+  policy: OutputPolicy,
+  artifacts: Vec<Artifact>,
+}
+
+impl Outputs {
+  pub fn new(policy: OutputPolicy) -> Outputs {
+    Outputs {
+      policy: policy,
+      artifacts: vec![],
+    }
+  }
+
+  /// Files exported so far, in the order they were written
+  pub fn artifacts(&self) -> &[Artifact] {
+    &self.artifacts
+  }
+
+  /// Forgets the files exported so far, e.g. before running another program
+  pub fn clear(&mut self) {
+    self.artifacts.clear();
+  }
+
+  /// Works out where a file named by the program goes under the output
+  /// root, refusing absolute paths and paths that climb out with `..`
+  fn resolve(&self, name: &str) -> Result<PathBuf, RuntimeError> {
+    let path = Path::new(name);
+    if name.is_empty() || path.file_name().is_none() {
+      return Err(RuntimeError::new(
+        ErrorKind::Output,
+        format!("`{}` is not a file name", name),
+      ));
+    }
+    for component in path.components() {
+      match component {
+        Component::Normal(_) | Component::CurDir => {}
+        Component::ParentDir => {
+          return Err(RuntimeError::new(
+            ErrorKind::Output,
+            format!("Output path `{}` can't use `..`", name),
+          ))
+        }
+        Component::RootDir | Component::Prefix(_) => {
+          return Err(RuntimeError::new(
+            ErrorKind::Output,
+            format!("Output path `{}` must be relative", name),
+          ))
+        }
+      }
+    }
+    Ok(self.policy.root.join(path))
+  }
+
+  /// Creates the directory a file goes in, making sure that symlinks don't
+  /// lead it, or the file itself, outside of the output root
+  fn confine(&self, name: &str, path: &Path) -> Result<(), RuntimeError> {
+    let io_error = |e: io::Error| {
+      RuntimeError::new(
+        ErrorKind::Output,
+        format!("Couldn't write {}: {}", path.display(), e),
+      )
+    };
+    let escaped = || {
+      RuntimeError::new(
+        ErrorKind::Output,
+        format!(
+          "Output path `{}` leads outside of the output directory",
+          name
+        ),
+      )
+    };
+    fs::create_dir_all(&self.policy.root).map_err(io_error)?;
+    let root = self.policy.root.canonicalize().map_err(io_error)?;
+    let dir = path.parent().unwrap_or(&self.policy.root);
+    // check the part of the directory that already exists before creating
+    // the rest, so nothing is created outside of the root either
+    let mut existing = dir;
+    while !existing.exists() {
+      existing = existing.parent().unwrap_or(&self.policy.root);
+    }
+    if !existing
+      .canonicalize()
+      .map_err(io_error)?
+      .starts_with(&root)
+    {
+      return Err(escaped());
+    }
+    fs::create_dir_all(dir).map_err(io_error)?;
+    if !dir.canonicalize().map_err(io_error)?.starts_with(&root) {
+      return Err(escaped());
+    }
+    let is_link = fs::symlink_metadata(path)
+      .map(|meta| meta.file_type().is_symlink())
+      .unwrap_or(false);
+    if is_link {
+      return Err(RuntimeError::new(
+        ErrorKind::Output,
+        format!("Output path `{}` is a symlink", name),
+      ));
+    }
+    Ok(())
+  }
+
+  /// Exports a file, creating any directories it needs under the output
+  /// root. `write` writes the contents and returns the number of triangles
+  /// written. In a dry run the contents are thrown away, but the file is
+  /// still recorded.
+  pub fn export<F>(
+    &mut self,
+    name: &str,
+    format: &'static str,
+    write: F,
+  ) -> Result<PathBuf, RuntimeError>
+  where
+    F: FnOnce(&mut dyn Write) -> io::Result<usize>,
+  {
+    let path = self.resolve(name)?;
+    let triangles = if self.policy.dry_run {
+      write(&mut io::sink())
+    } else {
+      self.confine(name, &path)?;
+      write_file(&path, write)
+    };
+    let triangles = triangles.map_err(|e| {
+      RuntimeError::new(
+        ErrorKind::Output,
+        format!("Couldn't write {}: {}", path.display(), e),
+      )
+    })?;
+    self.artifacts.push(Artifact {
+      path: path.clone(),
+      format: format,
+      triangles: triangles,
+    });
+    Ok(path)
+  }
+}
+
+fn write_file<F>(path: &Path, write: F) -> io::Result<usize>
+where
+  F: FnOnce(&mut dyn Write) -> io::Result<usize>,
+{
+  let mut file = BufWriter::new(fs::File::create(path)?);
+  let triangles = write(&mut file)?;
+  file.flush()?;
+  Ok(triangles)
+}
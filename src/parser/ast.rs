@@ -1,16 +1,38 @@
+use std::fmt;
+use units::{Axis, Unit};
+
 pub type Program = Vec<Meta<Stmt>>;
 
+/// A range of byte offsets into the source, from `start` up to but not
+/// including `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Meta<T> {
   pub inside: T,
+  /// Where the node starts in the source
   pub byte_offset: usize,
+  /// Where the node ends in the source
+  pub end: usize,
 }
 
 impl<T> Meta<T> {
-  pub fn new(inside: T, byte_offset: usize) -> Meta<T> {
+  pub fn new(inside: T, byte_offset: usize, end: usize) -> Meta<T> {
     Meta {
       inside: inside,
       byte_offset: byte_offset,
+      end: end,
+    }
+  }
+
+  pub fn span(&self) -> Span {
+    Span {
+      start: self.byte_offset,
+      end: self.end,
     }
   }
 }
@@ -20,26 +42,151 @@ pub enum Expr {
   Binary(Operator, Box<Meta<Expr>>, Box<Meta<Expr>>),
   Unary(Operator, Box<Meta<Expr>>),
   Number(f64),
+  /// A number with a unit suffix, as written in the source
+  Quantity(f64, Unit),
+  /// A number with an axis suffix, e.g. `2X`, measured in millimeters
+  Vector(f64, Axis),
+  Bool(bool),
   Str(String),
+  /// A string with interpolations, as the expressions whose text is joined
+  /// to make it. Literal parts are `Str`s.
+  Interpolate(Vec<Meta<Expr>>),
   Identifier(String),
-  FunctionCall(String, Vec<Meta<Expr>>),
+  FunctionCall(String, Vec<Argument>),
+  /// An anonymous function `fn (params) -> return_type { body }`
+  Function(Vec<Param>, Option<Type>, Box<Meta<Stmt>>),
+  /// `receiver.name(args)`
+  MethodCall(Box<Meta<Expr>>, String, Vec<Argument>),
+  /// `object.name`
+  Member(Box<Meta<Expr>>, String),
+  /// `object[index]`
+  Index(Box<Meta<Expr>>, Box<Meta<Expr>>),
+  /// `object[start:end]`, where either bound may be left out
+  Slice(
+    Box<Meta<Expr>>,
+    Option<Box<Meta<Expr>>>,
+    Option<Box<Meta<Expr>>>,
+  ),
   List(Vec<Meta<Expr>>),
+  /// `{key: value, ...}`
+  Map(Vec<(String, Meta<Expr>)>),
+  /// start, end, optional step, and whether the end is included
+  Range(
+    Box<Meta<Expr>>,
+    Box<Meta<Expr>>,
+    Option<Box<Meta<Expr>>>,
+    bool,
+  ),
+}
+
+/// An argument in a function call, either given by position or by
+/// parameter name (`name=expr`)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Argument {
+  Positional(Meta<Expr>),
+  Named(String, Meta<Expr>),
+}
+
+/// A parameter of a function declaration, with the expression for its
+/// default value if it has one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Param {
+  pub name: String,
+  /// Span of the parameter's name
+  pub span: Span,
+  pub ty: Option<Type>,
+  pub default: Option<Meta<Expr>>,
+}
+
+/// A type annotation on a parameter or the return value of a function
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Type {
+  /// `Object`, which any value matches
+  Any,
+  /// A plain number or a quantity with units
+  Number,
+  Bool,
+  Str,
+  Point,
+  Edge,
+  Plane,
+  Face,
+  Vector,
+  Solid,
+  List(Box<Type>),
+  Map,
+  Function,
+  None,
+}
+
+impl Type {
+  /// Looks up the type for a name in an annotation, other than `List`
+  pub fn from_name(name: &str) -> Option<Type> {
+    match name {
+      "Object" => Some(Type::Any),
+      "Number" => Some(Type::Number),
+      "Bool" => Some(Type::Bool),
+      "Str" => Some(Type::Str),
+      "Point" => Some(Type::Point),
+      "Edge" => Some(Type::Edge),
+      "Plane" => Some(Type::Plane),
+      "Face" => Some(Type::Face),
+      "Vector" => Some(Type::Vector),
+      "Solid" => Some(Type::Solid),
+      "List" => Some(Type::List(Box::new(Type::Any))),
+      "Map" => Some(Type::Map),
+      "Function" => Some(Type::Function),
+      "None" => Some(Type::None),
+      _ => None,
+    }
+  }
+
+  /// Whether a value of type `other` can be used where this type is expected
+  pub fn accepts(&self, other: &Type) -> bool {
+    match (self, other) {
+      (Type::Any, _) | (_, Type::Any) => true,
+      (Type::List(a), Type::List(b)) => a.accepts(b),
+      (a, b) => a == b,
+    }
+  }
+}
+
+impl fmt::Display for Type {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Type::Any => write!(f, "Object"),
+      Type::List(element) => write!(f, "List<{}>", element),
+      other => write!(f, "{:?}", other),
+    }
+  }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum Stmt {
   Block(Vec<Meta<Stmt>>),
-  If(Meta<Expr>, Box<Meta<Stmt>>),
+  If(Meta<Expr>, Box<Meta<Stmt>>, Option<Box<Meta<Stmt>>>),
   For(
     Box<Meta<Stmt>>,
     Meta<Expr>,
     Box<Meta<Stmt>>,
     Box<Meta<Stmt>>,
   ),
-  Return(Meta<Expr>),
+  While(Meta<Expr>, Box<Meta<Stmt>>),
+  ForIn(String, Meta<Expr>, Box<Meta<Stmt>>),
+  Break,
+  Continue,
+  /// `return value;`, or `return;` to return none
+  Return(Option<Meta<Expr>>),
   Expr(Meta<Expr>),
-  Function(String, Vec<String>, Box<Meta<Stmt>>),
+  /// `fn name(params) -> return_type { body }`
+  Function(String, Vec<Param>, Option<Type>, Box<Meta<Stmt>>),
   Assign(String, Meta<Expr>),
+  /// `[a, b] = expr`
+  Destructure(Vec<String>, Meta<Expr>),
+  /// `import "path";` or `import "path" as name;`
+  Import(String, Option<String>),
+  /// A statement that couldn't be parsed
+  Error,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize)]
@@ -50,4 +197,15 @@ pub enum Operator {
   Subtract,
   Mod,
   Negate,
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  And,
+  Or,
+  Not,
+  Union,
+  Intersection,
 }
@@ -0,0 +1,659 @@
+use std::fmt;
+use units::{Axis, Unit};
+
+/// A token along with the byte offsets of its start and end, as expected by
+/// the generated parser
+pub type Spanned<Tok, Loc, Error> = Result<(Loc, Tok, Loc), Error>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tok {
+  Number(f64),
+  /// A number followed by a unit suffix
+  Quantity(f64, Unit),
+  /// A number followed by an axis suffix, e.g. `2X`
+  Vector(f64, Axis),
+  Str(String),
+  /// The text of a string literal before its first interpolation `${`
+  StrStart(String),
+  /// The text between the `}` of one interpolation and the `${` of the next
+  StrMid(String),
+  /// The text between the `}` of the last interpolation and the closing quote
+  StrEnd(String),
+  Identifier(String),
+  // keywords
+  If,
+  Else,
+  For,
+  In,
+  While,
+  Break,
+  Continue,
+  Return,
+  Fn,
+  True,
+  False,
+  Step,
+  Import,
+  As,
+  // punctuation
+  LParen,
+  RParen,
+  LBrace,
+  RBrace,
+  LBracket,
+  RBracket,
+  Comma,
+  Colon,
+  Semicolon,
+  Assign,
+  Plus,
+  Minus,
+  Star,
+  Slash,
+  Percent,
+  Bang,
+  EqEq,
+  NotEq,
+  Less,
+  LessEq,
+  Greater,
+  GreaterEq,
+  AndAnd,
+  OrOr,
+  Pipe,
+  Ampersand,
+  PlusAssign,
+  MinusAssign,
+  PipeAssign,
+  AmpersandAssign,
+  Dot,
+  DotDot,
+  DotDotEq,
+  Arrow,
+  /// Stands in for text that couldn't be lexed, so the parser can recover
+  /// from it like any other unexpected token
+  Error,
+}
+
+impl fmt::Display for Tok {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Tok::Number(n) => write!(f, "{}", n),
+      Tok::Quantity(n, unit) => write!(f, "{}{:?}", n, unit),
+      Tok::Vector(n, axis) => write!(f, "{}{:?}", n, axis),
+      Tok::Str(s) => write!(f, "\"{}\"", s),
+      Tok::StrStart(s) => write!(f, "\"{}${{", s),
+      Tok::StrMid(s) => write!(f, "}}{}${{", s),
+      Tok::StrEnd(s) => write!(f, "}}{}\"", s),
+      Tok::Identifier(s) => write!(f, "{}", s),
+      other => {
+        let text = match other {
+          Tok::If => "if",
+          Tok::Else => "else",
+          Tok::For => "for",
+          Tok::In => "in",
+          Tok::While => "while",
+          Tok::Break => "break",
+          Tok::Continue => "continue",
+          Tok::Return => "return",
+          Tok::Fn => "fn",
+          Tok::True => "true",
+          Tok::False => "false",
+          Tok::Step => "step",
+          Tok::Import => "import",
+          Tok::As => "as",
+          Tok::LParen => "(",
+          Tok::RParen => ")",
+          Tok::LBrace => "{",
+          Tok::RBrace => "}",
+          Tok::LBracket => "[",
+          Tok::RBracket => "]",
+          Tok::Comma => ",",
+          Tok::Colon => ":",
+          Tok::Semicolon => ";",
+          Tok::Assign => "=",
+          Tok::Plus => "+",
+          Tok::Minus => "-",
+          Tok::Star => "*",
+          Tok::Slash => "/",
+          Tok::Percent => "%",
+          Tok::Bang => "!",
+          Tok::EqEq => "==",
+          Tok::NotEq => "!=",
+          Tok::Less => "<",
+          Tok::LessEq => "<=",
+          Tok::Greater => ">",
+          Tok::GreaterEq => ">=",
+          Tok::AndAnd => "&&",
+          Tok::OrOr => "||",
+          Tok::Pipe => "|",
+          Tok::Ampersand => "&",
+          Tok::PlusAssign => "+=",
+          Tok::MinusAssign => "-=",
+          Tok::PipeAssign => "|=",
+          Tok::AmpersandAssign => "&=",
+          Tok::Dot => ".",
+          Tok::DotDot => "..",
+          Tok::DotDotEq => "..=",
+          Tok::Arrow => "->",
+          Tok::Error => "invalid token",
+          _ => unreachable!(),
+        };
+        write!(f, "{}", text)
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexicalError {
+  UnexpectedChar(char, usize),
+  UnterminatedString(usize),
+  InvalidEscape(char, usize),
+  UnterminatedComment(usize),
+  UnknownUnit(String, usize),
+  InvalidNumber(String, usize),
+}
+
+impl LexicalError {
+  /// Byte offset into the source at which the error starts
+  pub fn location(&self) -> usize {
+    match *self {
+      LexicalError::UnexpectedChar(_, location)
+      | LexicalError::UnterminatedString(location)
+      | LexicalError::InvalidEscape(_, location)
+      | LexicalError::UnterminatedComment(location)
+      | LexicalError::UnknownUnit(_, location)
+      | LexicalError::InvalidNumber(_, location) => location,
+    }
+  }
+}
+
+impl fmt::Display for LexicalError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LexicalError::UnexpectedChar(c, _) => write!(f, "Unexpected character {:?}", c),
+      LexicalError::UnterminatedString(_) => write!(f, "Unterminated string"),
+      LexicalError::InvalidEscape(c, _) => write!(f, "Invalid escape sequence \\{}", c),
+      LexicalError::UnterminatedComment(_) => write!(f, "Unterminated block comment"),
+      LexicalError::UnknownUnit(suffix, _) => write!(f, "Unknown unit suffix {:?}", suffix),
+      LexicalError::InvalidNumber(msg, _) => write!(f, "Invalid number: {}", msg),
+    }
+  }
+}
+
+/// Turns source code into tokens, skipping whitespace and comments. Line
+/// comments start with `//`; block comments are delimited by `/*` and `*/`
+/// and may be nested.
+///
+/// A string containing interpolations such as `"a${x}b${y}c"` is split into
+/// `StrStart("a")`, the tokens of `x`, `StrMid("b")`, the tokens of `y` and
+/// `StrEnd("c")`.
+pub struct Lexer<'input> {
+  input: &'input str,
+  pos: usize,
+  /// For each interpolation being lexed, innermost last, the number of
+  /// braces opened inside it that haven't been closed yet
+  interpolations: Vec<usize>,
+}
+
+impl<'input> Lexer<'input> {
+  pub fn new(input: &'input str) -> Lexer<'input> {
+    Lexer {
+      input: input,
+      pos: 0,
+      interpolations: vec![],
+    }
+  }
+
+  /// Returns the character `n` characters past the current position
+  fn peek_nth(&self, n: usize) -> Option<char> {
+    self.input[self.pos..].chars().nth(n)
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.peek_nth(0)
+  }
+
+  fn bump(&mut self) -> Option<char> {
+    let c = self.peek()?;
+    self.pos += c.len_utf8();
+    Some(c)
+  }
+
+  /// Advances while the predicate holds, returning the consumed text
+  fn take_while<F: Fn(char) -> bool>(&mut self, pred: F) -> &'input str {
+    let start = self.pos;
+    while let Some(c) = self.peek() {
+      if !pred(c) {
+        break;
+      }
+      self.bump();
+    }
+    &self.input[start..self.pos]
+  }
+
+  fn skip_whitespace_and_comments(&mut self) -> Result<(), LexicalError> {
+    loop {
+      match (self.peek(), self.peek_nth(1)) {
+        (Some(c), _) if c.is_whitespace() => {
+          self.bump();
+        }
+        (Some('/'), Some('/')) => {
+          self.take_while(|c| c != '\n');
+        }
+        (Some('/'), Some('*')) => self.skip_block_comment()?,
+        _ => return Ok(()),
+      }
+    }
+  }
+
+  fn skip_block_comment(&mut self) -> Result<(), LexicalError> {
+    let start = self.pos;
+    let mut depth = 0;
+    loop {
+      match (self.peek(), self.peek_nth(1)) {
+        (Some('/'), Some('*')) => {
+          self.pos += 2;
+          depth += 1;
+        }
+        (Some('*'), Some('/')) => {
+          self.pos += 2;
+          depth -= 1;
+          if depth == 0 {
+            return Ok(());
+          }
+        }
+        (Some(_), _) => {
+          self.bump();
+        }
+        (None, _) => return Err(LexicalError::UnterminatedComment(start)),
+      }
+    }
+  }
+
+  /// Lexes a numeric literal. Decimal literals may have a fractional part
+  /// and an exponent (`1.5e-3`); `0x`, `0o` and `0b` prefixes give
+  /// hexadecimal, octal and binary integers. Digits may be separated with
+  /// underscores (`1_000`).
+  fn lex_number(&mut self) -> Result<Tok, LexicalError> {
+    let start = self.pos;
+    let radix = match (self.peek(), self.peek_nth(1)) {
+      (Some('0'), Some('x')) => Some((16, "hexadecimal")),
+      (Some('0'), Some('o')) => Some((8, "octal")),
+      (Some('0'), Some('b')) => Some((2, "binary")),
+      _ => None,
+    };
+    if let Some((radix, name)) = radix {
+      self.pos += 2;
+      // take any trailing letters too, so that bad digits are reported
+      // rather than lexed as a unit suffix
+      let digits = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+      let invalid = |msg: String| LexicalError::InvalidNumber(msg, start);
+      if let Some(c) = digits.chars().find(|&c| c != '_' && !c.is_digit(radix)) {
+        return Err(invalid(format!(
+          "invalid digit {:?} in {} literal",
+          c, name
+        )));
+      }
+      let digits = digits.replace('_', "");
+      if digits.is_empty() {
+        return Err(invalid(format!("missing digits in {} literal", name)));
+      }
+      return match u64::from_str_radix(&digits, radix) {
+        Ok(n) => Ok(Tok::Number(n as f64)),
+        Err(_) => Err(invalid(format!("{} literal is too large", name))),
+      };
+    }
+
+    let integer = self.take_while(|c| c.is_ascii_digit() || c == '_');
+    if integer.len() > 1 && integer.starts_with('0') {
+      return Err(LexicalError::InvalidNumber(
+        "leading zeros are not allowed, use the 0o prefix for octal".to_string(),
+        start,
+      ));
+    }
+    if self.peek() == Some('.') && self.peek_nth(1).map_or(false, |c| c.is_ascii_digit()) {
+      self.bump();
+      self.take_while(|c| c.is_ascii_digit() || c == '_');
+    }
+    let has_exponent = match (self.peek(), self.peek_nth(1), self.peek_nth(2)) {
+      (Some('e'), Some(c), _) | (Some('E'), Some(c), _) if c.is_ascii_digit() => true,
+      (Some('e'), Some('+'), Some(c))
+      | (Some('e'), Some('-'), Some(c))
+      | (Some('E'), Some('+'), Some(c))
+      | (Some('E'), Some('-'), Some(c)) => c.is_ascii_digit(),
+      _ => false,
+    };
+    if has_exponent {
+      self.pos += 2;
+      self.take_while(|c| c.is_ascii_digit() || c == '_');
+    }
+    let literal = self.input[start..self.pos].replace('_', "");
+    match literal.parse::<f64>() {
+      Ok(n) if n.is_finite() => Ok(Tok::Number(n)),
+      _ => Err(LexicalError::InvalidNumber(
+        "number literal is too large".to_string(),
+        start,
+      )),
+    }
+  }
+
+  /// Attaches a unit or an axis to a number if a suffix immediately follows it
+  fn lex_unit_suffix(&mut self, number: Tok) -> Result<Tok, LexicalError> {
+    let start = self.pos;
+    let suffix = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+    if suffix.is_empty() {
+      return Ok(number);
+    }
+    if let (Tok::Number(n), Some(axis)) = (&number, Axis::from_suffix(suffix)) {
+      return Ok(Tok::Vector(*n, axis));
+    }
+    match (number, Unit::from_suffix(suffix)) {
+      (Tok::Number(n), Some(unit)) => Ok(Tok::Quantity(n, unit)),
+      _ => Err(LexicalError::UnknownUnit(suffix.to_string(), start)),
+    }
+  }
+
+  fn lex_string(&mut self) -> Result<Tok, LexicalError> {
+    let start = self.pos;
+    self.bump();
+    self.lex_string_part(start, false)
+  }
+
+  /// Lexes the text of a string up to the closing quote or the next `${`.
+  /// `resumed` is set when continuing a string after an interpolation. An
+  /// invalid escape is reported once the whole part has been read, so that
+  /// lexing resumes after it.
+  fn lex_string_part(&mut self, start: usize, resumed: bool) -> Result<Tok, LexicalError> {
+    let mut contents = String::new();
+    let mut error = None;
+    loop {
+      let tok = match self.bump() {
+        None => return Err(LexicalError::UnterminatedString(start)),
+        Some('"') if resumed => Tok::StrEnd(contents),
+        Some('"') => Tok::Str(contents),
+        Some('$') if self.peek() == Some('{') => {
+          self.bump();
+          self.interpolations.push(0);
+          if resumed {
+            Tok::StrMid(contents)
+          } else {
+            Tok::StrStart(contents)
+          }
+        }
+        Some('\\') => {
+          let escape_start = self.pos - 1;
+          match self.bump() {
+            Some('n') => contents.push('\n'),
+            Some('t') => contents.push('\t'),
+            Some('r') => contents.push('\r'),
+            Some('0') => contents.push('\0'),
+            Some(c @ '\\') | Some(c @ '"') | Some(c @ '$') => contents.push(c),
+            Some(c) => {
+              error = error.or(Some(LexicalError::InvalidEscape(c, escape_start)));
+            }
+            None => return Err(LexicalError::UnterminatedString(start)),
+          }
+          continue;
+        }
+        Some(c) => {
+          contents.push(c);
+          continue;
+        }
+      };
+      return match error {
+        Some(error) => Err(error),
+        None => Ok(tok),
+      };
+    }
+  }
+
+  fn lex_word(&mut self) -> Tok {
+    let word = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+    match word {
+      "if" => Tok::If,
+      "else" => Tok::Else,
+      "for" => Tok::For,
+      "in" => Tok::In,
+      "while" => Tok::While,
+      "break" => Tok::Break,
+      "continue" => Tok::Continue,
+      "return" => Tok::Return,
+      "fn" => Tok::Fn,
+      "true" => Tok::True,
+      "false" => Tok::False,
+      "step" => Tok::Step,
+      "import" => Tok::Import,
+      "as" => Tok::As,
+      _ => Tok::Identifier(word.to_string()),
+    }
+  }
+
+  fn lex_punctuation(&mut self, c: char) -> Result<Tok, LexicalError> {
+    let next = self.peek_nth(1);
+    let (tok, len) = match (c, next) {
+      ('=', Some('=')) => (Tok::EqEq, 2),
+      ('!', Some('=')) => (Tok::NotEq, 2),
+      ('<', Some('=')) => (Tok::LessEq, 2),
+      ('>', Some('=')) => (Tok::GreaterEq, 2),
+      ('&', Some('&')) => (Tok::AndAnd, 2),
+      ('|', Some('|')) => (Tok::OrOr, 2),
+      ('+', Some('=')) => (Tok::PlusAssign, 2),
+      ('-', Some('=')) => (Tok::MinusAssign, 2),
+      ('-', Some('>')) => (Tok::Arrow, 2),
+      ('|', Some('=')) => (Tok::PipeAssign, 2),
+      ('&', Some('=')) => (Tok::AmpersandAssign, 2),
+      ('.', Some('.')) => {
+        if self.peek_nth(2) == Some('=') {
+          (Tok::DotDotEq, 3)
+        } else {
+          (Tok::DotDot, 2)
+        }
+      }
+      ('.', _) => (Tok::Dot, 1),
+      ('(', _) => (Tok::LParen, 1),
+      (')', _) => (Tok::RParen, 1),
+      ('{', _) => (Tok::LBrace, 1),
+      ('}', _) => (Tok::RBrace, 1),
+      ('[', _) => (Tok::LBracket, 1),
+      (']', _) => (Tok::RBracket, 1),
+      (',', _) => (Tok::Comma, 1),
+      (':', _) => (Tok::Colon, 1),
+      (';', _) => (Tok::Semicolon, 1),
+      ('=', _) => (Tok::Assign, 1),
+      ('+', _) => (Tok::Plus, 1),
+      ('-', _) => (Tok::Minus, 1),
+      ('*', _) => (Tok::Star, 1),
+      ('/', _) => (Tok::Slash, 1),
+      ('%', _) => (Tok::Percent, 1),
+      ('!', _) => (Tok::Bang, 1),
+      ('|', _) => (Tok::Pipe, 1),
+      ('&', _) => (Tok::Ampersand, 1),
+      ('<', _) => (Tok::Less, 1),
+      ('>', _) => (Tok::Greater, 1),
+      _ => return Err(LexicalError::UnexpectedChar(c, self.pos)),
+    };
+    self.pos += len;
+    Ok(tok)
+  }
+}
+
+impl<'input> Iterator for Lexer<'input> {
+  type Item = Spanned<Tok, usize, LexicalError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if let Err(e) = self.skip_whitespace_and_comments() {
+      // nothing left to lex after an unterminated comment
+      self.pos = self.input.len();
+      return Some(Err(e));
+    }
+    let start = self.pos;
+    let c = self.peek()?;
+    let tok = if c.is_ascii_digit() {
+      self
+        .lex_number()
+        .and_then(|number| self.lex_unit_suffix(number))
+    } else if c == '"' {
+      self.lex_string()
+    } else if c.is_ascii_alphabetic() {
+      Ok(self.lex_word())
+    } else if c == '}' && self.interpolations.last() == Some(&0) {
+      // the end of an interpolation, so the string continues
+      self.interpolations.pop();
+      self.bump();
+      self.lex_string_part(start, true)
+    } else {
+      let tok = self.lex_punctuation(c);
+      if let Some(depth) = self.interpolations.last_mut() {
+        match tok {
+          Ok(Tok::LBrace) => *depth += 1,
+          Ok(Tok::RBrace) => *depth -= 1,
+          _ => (),
+        }
+      }
+      tok
+    };
+    match tok {
+      Ok(tok) => Some(Ok((start, tok, self.pos))),
+      Err(e) => {
+        // skip the offending character so lexing can continue
+        if self.pos == start {
+          self.bump();
+        }
+        Some(Err(e))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lex(s: &str) -> Vec<(usize, Tok, usize)> {
+    Lexer::new(s).map(|t| t.unwrap()).collect()
+  }
+
+  #[test]
+  fn test_comments() {
+    assert_eq!(
+      lex("a // comment\nb"),
+      vec![
+        (0, Tok::Identifier("a".to_string()), 1),
+        (13, Tok::Identifier("b".to_string()), 14),
+      ]
+    );
+    assert_eq!(
+      lex("/* outer /* inner */ still a comment */ 1"),
+      vec![(40, Tok::Number(1.0), 41)],
+      "block comments nest"
+    );
+    assert_eq!(
+      Lexer::new("1 /* /* */").last(),
+      Some(Err(LexicalError::UnterminatedComment(2)))
+    );
+  }
+
+  #[test]
+  fn test_unit_suffixes() {
+    assert_eq!(
+      lex("10mm 0..2turn"),
+      vec![
+        (0, Tok::Quantity(10.0, Unit::Millimeter), 4),
+        (5, Tok::Number(0.0), 6),
+        (6, Tok::DotDot, 8),
+        (8, Tok::Quantity(2.0, Unit::Turn), 13),
+      ]
+    );
+    assert_eq!(
+      lex("2X -1.5Z"),
+      vec![
+        (0, Tok::Vector(2.0, Axis::X), 2),
+        (3, Tok::Minus, 4),
+        (4, Tok::Vector(1.5, Axis::Z), 8),
+      ]
+    );
+    assert_eq!(
+      Lexer::new("3ft").next(),
+      Some(Err(LexicalError::UnknownUnit("ft".to_string(), 1)))
+    );
+  }
+
+  #[test]
+  fn test_strings() {
+    let str = |s: &str| s.to_string();
+    assert_eq!(
+      lex(r#""tab\t \"quoted\" \${x}""#),
+      vec![(0, Tok::Str(str("tab\t \"quoted\" ${x}")), 24)]
+    );
+    assert_eq!(
+      lex(r#""a${x}b${ {c: "d${e}"}.c }f""#)
+        .into_iter()
+        .map(|(_, t, _)| t)
+        .collect::<Vec<_>>(),
+      vec![
+        Tok::StrStart(str("a")),
+        Tok::Identifier(str("x")),
+        Tok::StrMid(str("b")),
+        Tok::LBrace,
+        Tok::Identifier(str("c")),
+        Tok::Colon,
+        Tok::StrStart(str("d")),
+        Tok::Identifier(str("e")),
+        Tok::StrEnd(str("")),
+        Tok::RBrace,
+        Tok::Dot,
+        Tok::Identifier(str("c")),
+        Tok::StrEnd(str("f")),
+      ]
+    );
+    assert_eq!(
+      Lexer::new(r#""a\qb" 1"#).collect::<Vec<_>>(),
+      vec![
+        Err(LexicalError::InvalidEscape('q', 2)),
+        Ok((7, Tok::Number(1.0), 8))
+      ]
+    );
+  }
+
+  #[test]
+  fn test_numbers() {
+    let numbers = |s: &str| -> Vec<Tok> { lex(s).into_iter().map(|(_, t, _)| t).collect() };
+    assert_eq!(
+      numbers("0 0.5 1e-3 2.5E+2 1_000 0x1F 0o17 0b101 1e3mm"),
+      vec![
+        Tok::Number(0.0),
+        Tok::Number(0.5),
+        Tok::Number(0.001),
+        Tok::Number(250.0),
+        Tok::Number(1000.0),
+        Tok::Number(31.0),
+        Tok::Number(15.0),
+        Tok::Number(5.0),
+        Tok::Quantity(1000.0, Unit::Millimeter),
+      ]
+    );
+    let error = |s: &str| match Lexer::new(s).find(|t| t.is_err()) {
+      Some(Err(LexicalError::InvalidNumber(_, location))) => location,
+      other => panic!("expected an invalid number for {:?}, got {:?}", s, other),
+    };
+    assert_eq!(error("017"), 0, "leading zero");
+    assert_eq!(error("x = 0b102"), 4, "bad binary digit");
+    assert_eq!(error("0x"), 0, "no digits");
+    assert_eq!(error("0xFFFFFFFFFFFFFFFFF"), 0, "overflow");
+    assert_eq!(error("1e999"), 0, "overflow");
+  }
+
+  #[test]
+  fn test_ranges_and_numbers() {
+    assert_eq!(
+      lex("1..=2.5"),
+      vec![
+        (0, Tok::Number(1.0), 1),
+        (1, Tok::DotDotEq, 4),
+        (4, Tok::Number(2.5), 7),
+      ]
+    );
+  }
+}
@@ -1,31 +1,342 @@
-use super::ast::{Meta, Stmt};
-use super::util::get_line_number;
-use lalrpop_util::ParseError;
+use super::ast::{Meta, Param, Span, Stmt};
+use super::lexer::{Lexer, Tok};
+use super::util::get_col_line_number;
+use diagnostic::{Diagnostic, Label};
+use lalrpop_util::{ErrorRecovery, ParseError};
+use std::fmt;
 
 lalrpop_mod!(pub grammar, "/parser/grammar.rs");
 
-/// Parses a program and alerts on parse errors
-pub fn parse_program(program_string: &String) -> Option<Vec<Meta<Stmt>>> {
-  let maybe_ast = grammar::ProgramParser::new().parse(program_string);
-  match maybe_ast {
-    Ok(ast) => Some(ast),
+/// A problem found while lexing or parsing a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+  pub msg: String,
+  pub byte_offset: usize,
+  /// Byte offset just past the offending token
+  pub end: usize,
+  pub line: i32,
+  pub col: i32,
+  /// Tokens the parser would have accepted at this point, if known
+  pub expected: Vec<String>,
+}
+
+impl fmt::Display for SyntaxError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "Syntax Error at line {}, column {}: {}",
+      self.line, self.col, self.msg
+    )?;
+    if !self.expected.is_empty() {
+      write!(f, "; expected one of {}", self.expected.join(", "))?;
+    }
+    Ok(())
+  }
+}
+
+impl SyntaxError {
+  pub fn span(&self) -> Span {
+    Span {
+      start: self.byte_offset,
+      end: self.end,
+    }
+  }
+
+  /// Renders the error along with the source line it points at
+  pub fn render(&self, file: &str, program_string: &str) -> String {
+    let note = if self.expected.is_empty() {
+      String::new()
+    } else {
+      format!("expected one of {}", self.expected.join(", "))
+    };
+    Diagnostic {
+      severity: "error",
+      msg: self.msg.clone(),
+      primary: Some(Label {
+        file: file,
+        source: program_string,
+        span: self.span(),
+        msg: note,
+      }),
+      secondary: vec![],
+    }
+    .to_string()
+  }
+}
+
+/// A mistake in code that otherwise parses, which the grammar reports and
+/// carries on past
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrammarError {
+  /// A destructuring target that isn't a variable
+  InvalidAssignmentTarget(Span),
+  /// A type annotation that doesn't name a type
+  UnknownType(String, Span),
+  /// A parameter with the same name as an earlier one
+  DuplicateParam(String, Span),
+}
+
+impl GrammarError {
+  fn span(&self) -> Span {
+    match *self {
+      GrammarError::InvalidAssignmentTarget(span)
+      | GrammarError::UnknownType(_, span)
+      | GrammarError::DuplicateParam(_, span) => span,
+    }
+  }
+}
+
+impl fmt::Display for GrammarError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      GrammarError::InvalidAssignmentTarget(_) => write!(f, "Can only assign to a variable"),
+      GrammarError::UnknownType(name, _) => write!(f, "Unknown type {:?}", name),
+      GrammarError::DuplicateParam(name, _) => write!(f, "Duplicate parameter `{}`", name),
+    }
+  }
+}
+
+/// Wraps an error for the grammar to record without stopping
+pub fn recovered(error: GrammarError) -> ErrorRecovery<usize, Tok, GrammarError> {
+  ErrorRecovery {
+    error: ParseError::User { error: error },
+    dropped_tokens: vec![],
+  }
+}
+
+/// Reports parameters that reuse the name of an earlier parameter
+pub fn check_params(params: &[Param], errors: &mut Vec<ErrorRecovery<usize, Tok, GrammarError>>) {
+  for (i, param) in params.iter().enumerate() {
+    if params[..i].iter().any(|earlier| earlier.name == param.name) {
+      errors.push(recovered(GrammarError::DuplicateParam(
+        param.name.clone(),
+        param.span,
+      )));
+    }
+  }
+}
+
+/// A parsed program along with every syntax error found in it. Statements
+/// that couldn't be parsed are kept in the program as `Stmt::Error`, so the
+/// rest of it can still be analyzed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parsed {
+  pub program: Vec<Meta<Stmt>>,
+  pub errors: Vec<SyntaxError>,
+}
+
+/// Parses a program, recovering from syntax errors so that all of them are
+/// reported rather than just the first. Text that can't be lexed is reported
+/// and passed to the parser as `Tok::Error`, which it recovers from without
+/// reporting again.
+pub fn parse(program_string: &str) -> Parsed {
+  let mut errors = vec![];
+  let mut tokens = vec![];
+  for token in Lexer::new(program_string) {
+    match token {
+      Ok(token) => tokens.push(token),
+      Err(e) => {
+        let location = e.location();
+        let span = Span {
+          start: location,
+          end: location + 1,
+        };
+        errors.push(located_error(program_string, e.to_string(), span, vec![]));
+        tokens.push((span.start, Tok::Error, span.end));
+      }
+    }
+  }
+
+  let mut recovered = vec![];
+  let program = match grammar::ProgramParser::new().parse(&mut recovered, tokens) {
+    Ok(program) => program,
     Err(e) => {
-      match &e {
-        ParseError::UnrecognizedToken { token, .. } => {
-          if let Some((byte, ..)) = token {
-            let line = get_line_number(program_string, byte.clone());
-            println!("Syntax Error at line: {}", line);
-          } else {
-            println!("Syntax error: {:?}", e);
-          }
-        }
-        ParseError::InvalidToken { location } => {
-          let line = get_line_number(program_string, location.clone());
-          println!("Invalid token at line {}", line);
-        }
-        misc @ _ => println!("{:?}", misc),
+      recovered.push(ErrorRecovery {
+        error: e,
+        dropped_tokens: vec![],
+      });
+      vec![]
+    }
+  };
+  errors.extend(
+    recovered
+      .into_iter()
+      .filter(|recovery| !is_lexical(&recovery.error))
+      .map(|recovery| syntax_error(program_string, recovery.error)),
+  );
+  errors.sort_by_key(|error| error.byte_offset);
+  Parsed {
+    program: program,
+    errors: errors,
+  }
+}
+
+/// Parses a program, returning it only if there were no syntax errors
+pub fn parse_program(program_string: &str) -> Result<Vec<Meta<Stmt>>, Vec<SyntaxError>> {
+  let parsed = parse(program_string);
+  if parsed.errors.is_empty() {
+    Ok(parsed.program)
+  } else {
+    Err(parsed.errors)
+  }
+}
+
+/// Whether a parse error is about text the lexer already reported
+fn is_lexical(error: &ParseError<usize, Tok, GrammarError>) -> bool {
+  match error {
+    ParseError::UnrecognizedToken {
+      token: Some((_, Tok::Error, _)),
+      ..
+    } => true,
+    _ => false,
+  }
+}
+
+fn syntax_error(program_string: &str, error: ParseError<usize, Tok, GrammarError>) -> SyntaxError {
+  let (msg, start, end, expected) = match error {
+    ParseError::InvalidToken { location } => {
+      ("Invalid token".to_string(), location, location + 1, vec![])
+    }
+    ParseError::UnrecognizedToken {
+      token: Some((start, token, end)),
+      expected,
+    } => (format!("Unexpected `{}`", token), start, end, expected),
+    ParseError::UnrecognizedToken {
+      token: None,
+      expected,
+    } => (
+      "Unexpected end of file".to_string(),
+      program_string.len().saturating_sub(1),
+      program_string.len(),
+      expected,
+    ),
+    ParseError::ExtraToken {
+      token: (start, token, end),
+    } => (format!("Unexpected `{}`", token), start, end, vec![]),
+    ParseError::User { error } => {
+      let span = error.span();
+      (error.to_string(), span.start, span.end, vec![])
+    }
+  };
+  located_error(
+    program_string,
+    msg,
+    Span {
+      start: start,
+      end: end,
+    },
+    expected,
+  )
+}
+
+fn located_error(
+  program_string: &str,
+  msg: String,
+  span: Span,
+  expected: Vec<String>,
+) -> SyntaxError {
+  let (col, line) = get_col_line_number(program_string, span.start);
+  SyntaxError {
+    msg: msg,
+    byte_offset: span.start,
+    end: span.end,
+    line: line,
+    col: col,
+    expected: expected,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::ast::Expr;
+  use super::*;
+
+  #[test]
+  fn test_error_recovery() {
+    let parsed = parse("a = 1;\nb = ;\nprint(a);\nc = 2 +* 3;\nd = \"x\\q\";\ne = 4;");
+    let lines: Vec<(i32, i32)> = parsed.errors.iter().map(|e| (e.line, e.col)).collect();
+    // the string with a bad escape is only reported once, by the lexer
+    assert_eq!(lines, vec![(2, 5), (4, 8), (5, 7)]);
+    assert_eq!(parsed.errors[0].msg, "Unexpected `;`");
+    assert_eq!(parsed.errors[2].msg, "Invalid escape sequence \\q");
+    assert!(parsed.errors[0]
+      .expected
+      .contains(&"\"identifier\"".to_string()));
+    let statements: Vec<bool> = parsed
+      .program
+      .iter()
+      .map(|stmt| stmt.inside == Stmt::Error)
+      .collect();
+    assert_eq!(statements, vec![false, true, false, true, true, false]);
+
+    // mistakes the grammar notices itself don't stop it either
+    let parsed = parse("x = 1 +; [a, 1] = [1, 2]; fn f(a: Nope, a) {} y = ;");
+    let messages: Vec<&str> = parsed.errors.iter().map(|e| e.msg.as_str()).collect();
+    assert_eq!(
+      messages,
+      vec![
+        "Unexpected `;`",
+        "Can only assign to a variable",
+        "Unknown type \"Nope\"",
+        "Duplicate parameter `a`",
+        "Unexpected `;`",
+      ]
+    );
+
+    // blocks recover up to their closing brace, and the program keeps what
+    // parsed before a block left open at the end of the file
+    let parsed = parse("fn f() { a = 1; b = 2 }\nc = 0x;\nfn g() { d = 3;");
+    let messages: Vec<&str> = parsed.errors.iter().map(|e| e.msg.as_str()).collect();
+    assert_eq!(
+      messages,
+      vec![
+        "Unexpected `}`",
+        "Invalid number: missing digits in hexadecimal literal",
+        "Unexpected end of file",
+      ]
+    );
+    match parsed.program[0].inside {
+      Stmt::Function(_, _, _, ref body) => match body.inside {
+        Stmt::Block(ref stmts) => assert_eq!(stmts.len(), 2),
+        ref other => panic!("expected a block, got {:?}", other),
+      },
+      ref other => panic!("expected a function, got {:?}", other),
+    }
+    let statements: Vec<bool> = parsed
+      .program
+      .iter()
+      .map(|stmt| stmt.inside == Stmt::Error)
+      .collect();
+    assert_eq!(statements, vec![false, true, true]);
+  }
+
+  #[test]
+  fn test_synthetic_spans() {
+    let source = "s = \"a${x}bc${y}\"; n += 1;";
+    let program = parse_program(source).unwrap();
+    let text = |expr: &Meta<Expr>| &source[expr.byte_offset..expr.end];
+    match program[0].inside {
+      Stmt::Assign(
+        _,
+        Meta {
+          inside: Expr::Interpolate(ref parts),
+          ..
+        },
+      ) => {
+        let parts: Vec<&str> = parts.iter().map(text).collect();
+        assert_eq!(parts, vec!["\"a${", "x", "}bc${", "y", "}\""]);
       }
-      None
+      ref other => panic!("expected an interpolated string, got {:?}", other),
+    }
+    match program[1].inside {
+      Stmt::Assign(
+        _,
+        Meta {
+          inside: Expr::Binary(_, ref target, ref value),
+          ..
+        },
+      ) => assert_eq!((text(target), text(value)), ("n", "1")),
+      ref other => panic!("expected a compound assignment, got {:?}", other),
     }
   }
 }
@@ -1,26 +1,20 @@
-/// Returns the line number and column number tuple given a byte offset into a string
+/// Returns the column number and line number tuple given a byte offset into
+/// a string. Columns count characters rather than bytes. Both are -1 if the
+/// offset is out of bounds; an offset inside a character counts as that
+/// character.
 pub fn get_col_line_number(s: &str, byte_offset: usize) -> (i32, i32) {
-  let mut line_count = 1;
-  let mut col_count = 1;
-  let mut counter = 0;
-  if s == "" {
+  if byte_offset >= s.len() {
     return (-1, -1);
   }
-  for (i, c) in s.chars().enumerate() {
-    counter = i;
-    if i == byte_offset || i > byte_offset {
-      break;
-    } else if c == '\n' {
-      line_count += 1;
-      col_count = 0;
-    }
-    col_count += 1
-  }
-  if byte_offset == counter {
-    (col_count, line_count)
-  } else {
-    (-1, -1)
+  let mut start = byte_offset;
+  while !s.is_char_boundary(start) {
+    start -= 1;
   }
+  let before = &s[..start];
+  let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+  let line = before.matches('\n').count() + 1;
+  let col = before[line_start..].chars().count() + 1;
+  (col as i32, line as i32)
 }
 
 /// Gets the line number given a byte offset into a string
@@ -77,6 +71,11 @@ mod tests {
       "col number of newline"
     );
     assert_eq!(get_column_number(&"".to_string(), 0), -1, "empty string");
+    assert_eq!(
+      get_col_line_number(&"é\n«ab»".to_string(), 6),
+      (3, 2),
+      "columns count characters, not bytes"
+    );
     assert_eq!(
       get_column_number(&"foo".to_string(), 3),
       -1,
@@ -1,33 +1,59 @@
-use parser::ast::{Expr, Meta, Operator, Stmt};
-use parser::util::get_line_number;
+use boolean::{boolean, Boolean};
+use diagnostic::{Diagnostic, Label};
+use output::{Artifact, OutputPolicy, Outputs};
+use parser::ast::{Argument, Expr, Meta, Operator, Param, Span, Stmt, Type};
+use parser::parse::parse_program;
+use parser::util::{get_col_line_number, get_line_number};
 use solid::{Edge, Face, Plane, Point, Solid, Vector};
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fmt;
+use std::fs;
 use std::io;
 use std::io::Write;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
 use stdlib;
+use typecheck;
+use units::{Axis, Dimension};
 
 const CURRENT_FUNCTION_CALL_KEY: &'static str = "___CURRENT_FUNCTION_CALL";
 
 lazy_static! {
   // Note: these need to be hooked up to the actual definitions from
   // stdlib.rs in Runtime.run_stdlib_function_call()
-  static ref STD_LIB_FUNCTIONS: Vec<&'static str> = vec![
+  pub static ref STD_LIB_FUNCTIONS: Vec<&'static str> = vec![
     "print",
     "Box",
     "Plane",
     "move",
     "difference",
     "rotate_x",
+    "translate",
+    "Vector",
+    "Point",
+    "dot",
+    "cross",
+    "normalize",
+    "length",
     "display",
     "write_stl",
+    "len",
+    "format",
+    "str",
   ];
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Object {
   Number(f64),
+  /// A number with a dimension, stored in the base unit of that dimension
+  Quantity(f64, Dimension),
+  Bool(bool),
   Str(String),
   Point(Point),
   Edge(Edge),
@@ -36,22 +62,349 @@ pub enum Object {
   Vector(Vector),
   Solid(Solid),
   List(Vec<Object>),
+  Map(BTreeMap<String, Object>),
+  Function(Function),
+  /// The result of a function that doesn't return a value
+  None,
+}
+
+/// A function value, which can be stored in variables and passed around
+/// like any other object
+#[derive(Clone)]
+pub enum Function {
+  /// A function defined in source code
+  Closure(Rc<Closure>),
+  /// A function from the standard library, by name
+  StdLib(String),
+}
+
+/// A function defined in source code along with the scopes that were visible
+/// where it was defined, which its body runs in
+pub struct Closure {
+  name: Option<String>,
+  params: Vec<Param>,
+  ret: Option<Type>,
+  body: Meta<Stmt>,
+  env: Vec<Scope>,
+  source: Rc<Source>,
+  /// Where the function was defined, to point at when it's called wrongly
+  span: Span,
+}
+
+impl Function {
+  pub fn name(&self) -> &str {
+    match self {
+      Function::Closure(closure) => closure.name.as_ref().map_or("<anonymous>", |n| n.as_str()),
+      Function::StdLib(name) => name,
+    }
+  }
+}
+
+impl fmt::Debug for Function {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "<fn {}>", self.name())
+  }
+}
+
+impl PartialEq for Function {
+  /// Functions are only equal to themselves
+  fn eq(&self, other: &Function) -> bool {
+    match (self, other) {
+      (Function::Closure(a), Function::Closure(b)) => Rc::ptr_eq(a, b),
+      (Function::StdLib(a), Function::StdLib(b)) => a == b,
+      _ => false,
+    }
+  }
+}
+
+/// Evaluated arguments of a function call
+#[derive(Debug, Clone, PartialEq)]
+pub struct Args {
+  pub positional: Vec<Object>,
+  pub named: Vec<(String, Object)>,
+}
+
+impl Args {
+  /// Matches the arguments to a function's parameter names. Positional
+  /// arguments fill parameters in order and keyword arguments fill them by
+  /// name; it is an error to give an unknown name or to fill a parameter
+  /// twice.
+  pub fn bind<S: AsRef<str>>(self, function: &str, params: &[S]) -> Result<Params, RuntimeError> {
+    if self.positional.len() > params.len() {
+      return Err(RuntimeError::new(
+        ErrorKind::Argument,
+        format!(
+          "{} takes {} arguments but {} were given",
+          function,
+          params.len(),
+          self.positional.len()
+        ),
+      ));
+    }
+    let mut values = HashMap::new();
+    for (param, val) in params.iter().zip(self.positional) {
+      values.insert(param.as_ref().to_string(), val);
+    }
+    for (name, val) in self.named {
+      if !params.iter().any(|param| param.as_ref() == name) {
+        return Err(RuntimeError::new(
+          ErrorKind::Argument,
+          format!("{} has no parameter named `{}`", function, name),
+        ));
+      }
+      if values.contains_key(&name) {
+        return Err(RuntimeError::new(
+          ErrorKind::Argument,
+          format!("{} got multiple values for parameter `{}`", function, name),
+        ));
+      }
+      values.insert(name, val);
+    }
+    Ok(Params {
+      function: function.to_string(),
+      values: values,
+    })
+  }
+}
+
+/// Arguments of a function call matched up with parameter names
+#[derive(Debug, Clone, PartialEq)]
+pub struct Params {
+  function: String,
+  values: HashMap<String, Object>,
+}
+
+impl Params {
+  /// Takes the value of an optional parameter, if it was given
+  pub fn opt(&mut self, name: &str) -> Option<Object> {
+    self.values.remove(name)
+  }
+
+  /// Takes the value of a required parameter
+  pub fn get(&mut self, name: &str) -> Result<Object, RuntimeError> {
+    self.values.remove(name).ok_or_else(|| {
+      RuntimeError::new(
+        ErrorKind::Argument,
+        format!("{} is missing argument `{}`", self.function, name),
+      )
+    })
+  }
+}
+
+impl Object {
+  /// The annotation type that describes this object. Lists whose elements
+  /// all have the same type get that element type.
+  pub fn annotation_type(&self) -> Type {
+    match self {
+      Object::Number(_) | Object::Quantity(..) => Type::Number,
+      Object::Bool(_) => Type::Bool,
+      Object::Str(_) => Type::Str,
+      Object::Point(_) => Type::Point,
+      Object::Edge(_) => Type::Edge,
+      Object::Plane(_) => Type::Plane,
+      Object::Face(_) => Type::Face,
+      Object::Vector(_) => Type::Vector,
+      Object::Solid(_) => Type::Solid,
+      Object::List(l) => {
+        let mut types = l.iter().map(Object::annotation_type);
+        let first = types.next().unwrap_or(Type::Any);
+        if types.all(|ty| ty == first) {
+          Type::List(Box::new(first))
+        } else {
+          Type::List(Box::new(Type::Any))
+        }
+      }
+      Object::Map(_) => Type::Map,
+      Object::Function(_) => Type::Function,
+      Object::None => Type::None,
+    }
+  }
+
+  /// Name of the kind of object, for use in messages
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Object::Number(_) => "Number",
+      Object::Quantity(..) => "Quantity",
+      Object::Bool(_) => "Bool",
+      Object::Str(_) => "Str",
+      Object::Point(_) => "Point",
+      Object::Edge(_) => "Edge",
+      Object::Plane(_) => "Plane",
+      Object::Face(_) => "Face",
+      Object::Vector(_) => "Vector",
+      Object::Solid(_) => "Solid",
+      Object::List(_) => "List",
+      Object::Map(_) => "Map",
+      Object::Function(_) => "Function",
+      Object::None => "None",
+    }
+  }
+}
+
+/// What sort of problem a runtime error is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+  /// Running a statement that failed to parse
+  Syntax,
+  /// A variable or property that doesn't exist
+  Name,
+  /// A value of the wrong type for an operation
+  Type,
+  /// Arguments that don't match a function's parameters
+  Argument,
+  /// A value of the right type that is out of range or otherwise invalid
+  Value,
+  /// `break`, `continue` or `return` where it can't be used
+  Control,
+  /// A module that can't be found or loaded
+  Import,
+  /// A file that can't or may not be written
+  Output,
+  /// Running past one of the runtime's `Limits`
+  Limit,
+}
+
+impl fmt::Display for ErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      ErrorKind::Syntax => "syntax",
+      ErrorKind::Name => "name",
+      ErrorKind::Type => "type",
+      ErrorKind::Argument => "argument",
+      ErrorKind::Value => "value",
+      ErrorKind::Control => "control flow",
+      ErrorKind::Import => "import",
+      ErrorKind::Output => "output",
+      ErrorKind::Limit => "limit",
+    };
+    write!(f, "{}", name)
+  }
 }
 
 #[derive(Debug, Clone)]
 pub struct RuntimeError {
+  kind: ErrorKind,
   msg: String,
+  /// Where the error happened, once known. Errors from stdlib functions
+  /// are given the location of the call that raised them.
+  location: Option<Location>,
+  /// Other places worth pointing out, e.g. where a function was defined
+  notes: Vec<(Location, String)>,
+  /// Calls to functions defined in code that the error unwound through,
+  /// innermost first
+  stack: Vec<Frame>,
+}
+
+/// A call to a function defined in code, for tracebacks
+#[derive(Debug, Clone)]
+struct Frame {
+  function: String,
+  call_site: Location,
+}
+
+/// A span of a particular source
+#[derive(Clone)]
+struct Location {
+  source: Rc<Source>,
+  span: Span,
+}
+
+impl fmt::Debug for Location {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{}:{}..{}",
+      self.source.name(),
+      self.span.start,
+      self.span.end
+    )
+  }
 }
 
 impl RuntimeError {
-  fn new(msg: String) -> RuntimeError {
-    RuntimeError { msg: msg }
+  pub fn new(kind: ErrorKind, msg: String) -> RuntimeError {
+    RuntimeError {
+      kind: kind,
+      msg: msg,
+      location: None,
+      notes: vec![],
+      stack: vec![],
+    }
+  }
+
+  /// Renders the error along with the source lines it points at, followed
+  /// by the calls that led to it
+  pub fn render(&self) -> String {
+    let locations: Vec<(&Location, String, &str)> = self
+      .location
+      .iter()
+      .map(|location| (location, ""))
+      .chain(
+        self
+          .notes
+          .iter()
+          .map(|(location, msg)| (location, msg.as_str())),
+      )
+      .map(|(location, msg)| (location, location.source.name(), msg))
+      .collect();
+    let mut labels = locations.iter().map(|(location, file, msg)| Label {
+      file: file,
+      source: &location.source.code,
+      span: location.span,
+      msg: msg.to_string(),
+    });
+    let primary = if self.location.is_some() {
+      labels.next()
+    } else {
+      None
+    };
+    let severity = format!("{} error", self.kind);
+    let mut rendered = Diagnostic {
+      severity: &severity,
+      msg: self.msg.clone(),
+      primary: primary,
+      secondary: labels.collect(),
+    }
+    .to_string();
+    if !self.stack.is_empty() {
+      rendered.push_str("\ncall stack (innermost first):");
+    }
+    // deep recursion is summarized by its innermost and outermost calls
+    let shown = 10;
+    for (i, frame) in self.stack.iter().enumerate() {
+      if i >= shown && i + shown < self.stack.len() {
+        if i == shown {
+          let hidden = self.stack.len() - 2 * shown;
+          rendered.push_str(&format!("\n  ... {} more calls", hidden));
+        }
+        continue;
+      }
+      let (col, line) =
+        get_col_line_number(&frame.call_site.source.code, frame.call_site.span.start);
+      rendered.push_str(&format!(
+        "\n  {}: {} called at {}:{}:{}",
+        i,
+        frame.function,
+        frame.call_site.source.name(),
+        line,
+        col
+      ));
+    }
+    rendered
   }
 }
 
 impl fmt::Display for RuntimeError {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{}", self.msg)
+    write!(f, "Runtime Error: {}", self.msg)?;
+    if let Some(ref location) = self.location {
+      let line = get_line_number(&location.source.code, location.span.start);
+      write!(f, " at line {}", line)?;
+      if let Some(ref path) = location.source.path {
+        write!(f, " of {}", path.display())?;
+      }
+    }
+    Ok(())
   }
 }
 
@@ -65,23 +418,15 @@ impl Error for RuntimeError {
   }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum SymbolVal {
-  Function(Vec<String>, Meta<Stmt>),
-  StdLib(String),
-  Object(Object),
-}
-
 #[derive(Debug, Clone, PartialEq)]
 pub struct VarEntry {
   name: String,
-  value: SymbolVal,
+  value: Object,
 }
 
+/// Marks the scope holding the parameters of a function call
 #[derive(Debug, Clone, PartialEq)]
-pub struct FunctionMetadataEntry {
-  return_val: Option<Object>,
-}
+pub struct FunctionMetadataEntry {}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum SymbolEntry {
@@ -89,44 +434,280 @@ pub enum SymbolEntry {
   Function(FunctionMetadataEntry),
 }
 
+/// A level of the symbol table. Scopes are shared so that functions can keep
+/// the scopes they were defined in alive.
+type Scope = Rc<RefCell<HashMap<String, SymbolEntry>>>;
+
+/// How control leaves a statement
+#[derive(Debug, Clone, PartialEq)]
+enum Flow {
+  Normal,
+  Break,
+  Continue,
+  /// Unwinds to the enclosing function call with its result
+  Return(Object),
+}
+
+/// The code of a program or module, and the file it was read from if any
+struct Source {
+  code: String,
+  path: Option<PathBuf>,
+}
+
+impl Source {
+  /// Name of the source to show in error messages
+  fn name(&self) -> String {
+    match self.path {
+      Some(ref path) => path.display().to_string(),
+      None => "<stdin>".to_string(),
+    }
+  }
+}
+
+/// The numbers in a range, worked out one at a time so that a loop over a
+/// huge range doesn't need them all at once
+struct RangeIter {
+  start: f64,
+  end: f64,
+  step: f64,
+  inclusive: bool,
+  dim: Dimension,
+  /// Index of the next number
+  index: f64,
+}
+
+impl Iterator for RangeIter {
+  type Item = Object;
+
+  fn next(&mut self) -> Option<Object> {
+    // computing each number from the start avoids accumulating error
+    let x = self.start + self.index * self.step;
+    let in_range = match (self.step > 0.0, self.inclusive) {
+      (true, true) => x <= self.end,
+      (true, false) => x < self.end,
+      (false, true) => x >= self.end,
+      (false, false) => x > self.end,
+    };
+    if !in_range {
+      return None;
+    }
+    self.index += 1.0;
+    Some(make_quantity(x, self.dim))
+  }
+}
+
+/// Bounds on how much work a program may do before it's stopped, so that
+/// untrusted programs can be run safely. `None` means no limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+  /// Number of calls to functions defined in code that may be in progress
+  /// at once
+  pub max_call_depth: Option<usize>,
+  /// How deeply statements, expressions and function calls may be nested
+  /// while running. Each level uses some of the Rust stack, so this is what
+  /// keeps a program from overflowing it, as long as the program runs on a
+  /// thread with at least `stack_size()` of stack.
+  pub max_nesting: Option<usize>,
+  /// Number of steps a program may take in total. Running a statement or an
+  /// expression is a step, and so is adding each element of a range to a
+  /// list.
+  pub max_steps: Option<u64>,
+  /// How long the program may run for
+  pub max_time: Option<Duration>,
+}
+
+impl Default for Limits {
+  fn default() -> Limits {
+    Limits {
+      max_call_depth: Some(100),
+      max_nesting: Some(NESTING_LIMIT),
+      max_steps: None,
+      max_time: None,
+    }
+  }
+}
+
+impl Limits {
+  /// Stack a thread needs to run a program within these limits, or `None`
+  /// if nesting isn't limited or no stack could be that big
+  pub fn stack_size(&self) -> Option<usize> {
+    self
+      .max_nesting
+      .and_then(|max| max.checked_mul(STACK_PER_LEVEL))
+      .and_then(|size| size.checked_add(STACK_BASE))
+  }
+}
+
+/// Largest nesting limit that may be asked for on the command line, which
+/// needs a stack of about 800MB
+pub const MAX_NESTING_LIMIT: usize = 100_000;
+
+/// Most elements a range may have when it's made into a list. Loops over a
+/// range don't make a list, so they can go on for longer.
+const RANGE_LIST_LIMIT: usize = 1_000_000;
+/// Default for `Limits::max_nesting`
+const NESTING_LIMIT: usize = 1000;
+/// Stack used by each level of nesting, measured on a debug build with some
+/// room to spare
+const STACK_PER_LEVEL: usize = 8 * 1024;
+/// Stack used outside of the program being run
+const STACK_BASE: usize = 256 * 1024;
+
 pub struct Runtime {
-  symbol_table: Vec<HashMap<String, SymbolEntry>>,
-  source_code: String,
+  /// Scopes visible from the statement being run, innermost last. While a
+  /// function runs this is the chain of scopes it was defined in followed
+  /// by its own frame, so it can't see its caller's variables.
+  symbol_table: Vec<Scope>,
+  /// Every scope that is still alive. A function keeps the scope it is
+  /// stored in alive, so scopes holding functions are never freed on their
+  /// own; they're emptied when the runtime is dropped to break the cycle.
+  scopes: Vec<Weak<RefCell<HashMap<String, SymbolEntry>>>>,
+  /// Source of the code being run, used for error messages and to resolve
+  /// imports
+  source: Rc<Source>,
   stdout: Box<dyn Write>,
+  /// Number of loops enclosing the statement being run in the current function
+  loop_depth: usize,
+  /// Directories searched for imports that aren't found next to the
+  /// importing file
+  library_paths: Vec<PathBuf>,
+  /// Namespaces of modules that have been imported, by canonical path
+  modules: HashMap<PathBuf, Object>,
+  /// Files currently being loaded, outermost first, for detecting cycles
+  import_stack: Vec<PathBuf>,
+  limits: Limits,
+  /// Where files exported by the program go, and what has been exported
+  outputs: Outputs,
+  /// Number of calls to functions defined in code in progress
+  call_depth: usize,
+  /// Number of statements, expressions and calls being run, each inside the
+  /// last
+  nesting: usize,
+  /// Number of steps taken so far
+  steps_taken: u64,
+  /// When the program started running
+  started: Instant,
 }
 
 impl Runtime {
   pub fn new(source_code: String, stdout: Option<Box<dyn Write>>) -> Runtime {
-    Runtime {
-      symbol_table: vec![HashMap::new()],
-      source_code: source_code,
+    let mut runtime = Runtime {
+      symbol_table: vec![],
+      scopes: vec![],
+      source: Rc::new(Source {
+        code: source_code,
+        path: None,
+      }),
       stdout: stdout.unwrap_or(Box::new(io::stdout()) as Box<dyn Write>),
+      loop_depth: 0,
+      library_paths: vec![],
+      modules: HashMap::new(),
+      import_stack: vec![],
+      limits: Limits::default(),
+      outputs: Outputs::new(OutputPolicy::default()),
+      call_depth: 0,
+      nesting: 0,
+      steps_taken: 0,
+      started: Instant::now(),
+    };
+    runtime.symbol_table = vec![runtime.new_scope(HashMap::new())];
+    runtime
+  }
+
+  fn new_scope(&mut self, table: HashMap<String, SymbolEntry>) -> Scope {
+    let scope = Rc::new(RefCell::new(table));
+    // forget freed scopes now and then, so this doesn't grow with every call
+    if self.scopes.len() == self.scopes.capacity() {
+      self.scopes.retain(|scope| scope.upgrade().is_some());
+      let len = self.scopes.len();
+      self.scopes.reserve(len.max(16));
     }
+    self.scopes.push(Rc::downgrade(&scope));
+    scope
+  }
+
+  /// Sets the file the program was read from. Imports are resolved relative
+  /// to it.
+  pub fn set_file(&mut self, path: PathBuf) {
+    let path = path.canonicalize().unwrap_or(path);
+    self.import_stack = vec![path.clone()];
+    self.source = Rc::new(Source {
+      code: self.source.code.clone(),
+      path: Some(path),
+    });
+  }
+
+  /// Adds a directory to search for imported modules
+  pub fn add_library_path(&mut self, path: PathBuf) {
+    self.library_paths.push(path);
+  }
+
+  /// Sets how much work the program may do before it's stopped
+  pub fn set_limits(&mut self, limits: Limits) {
+    self.limits = limits;
+  }
+
+  /// Sets where the program may write files
+  pub fn set_output_policy(&mut self, policy: OutputPolicy) {
+    self.outputs = Outputs::new(policy);
+  }
+
+  /// Files exported by the program so far. If the program fails, these are
+  /// the files it wrote before failing.
+  pub fn artifacts(&self) -> &[Artifact] {
+    self.outputs.artifacts()
   }
 
-  /// Runs a program
-  pub fn run(&mut self, program: &Vec<Meta<Stmt>>) -> Result<(), RuntimeError> {
+  /// Runs a program, returning a manifest of the files it exported
+  pub fn run(&mut self, program: &Vec<Meta<Stmt>>) -> Result<Vec<Artifact>, RuntimeError> {
+    self.started = Instant::now();
+    self.steps_taken = 0;
+    self.nesting = 0;
+    self.outputs.clear();
     self.add_stdlib();
     for stmt in program {
       self.run_stmt(stmt)?;
     }
-    Ok(())
+    Ok(self.artifacts().to_vec())
   }
 
-  /// Runs any AST statement
-  fn run_stmt(&mut self, stmt: &Meta<Stmt>) -> Result<(), RuntimeError> {
+  /// Runs any AST statement, reporting how control left it
+  fn run_stmt(&mut self, stmt: &Meta<Stmt>) -> Result<Flow, RuntimeError> {
+    self.step(stmt.span())?;
+    self.enter(stmt.span())?;
+    let flow = self.dispatch_stmt(stmt);
+    self.nesting -= 1;
+    flow
+  }
+
+  fn dispatch_stmt(&mut self, stmt: &Meta<Stmt>) -> Result<Flow, RuntimeError> {
     match stmt.inside {
       Stmt::Block(ref stmts) => self.handle_block(stmts),
-      Stmt::Return(ref expr) => self.handle_return(expr),
+      Stmt::Return(ref expr) => self.handle_return(stmt, expr),
       Stmt::Expr(ref expr) => self.handle_expr(expr),
       Stmt::Assign(ref identifier, ref expr) => self.handle_assign(identifier.to_string(), expr),
-      Stmt::Function(ref identifier, ref params, ref stmt) => {
-        self.handle_function_declaration(identifier.to_string(), params, stmt)
+      Stmt::Destructure(ref identifiers, ref expr) => self.handle_destructure(identifiers, expr),
+      Stmt::Function(ref identifier, ref params, ref ret, ref body) => {
+        self.handle_function_declaration(identifier.to_string(), params, ret, body, stmt.span())
       }
       Stmt::For(ref assign, ref condition, ref inc, ref body) => {
         self.handle_for(assign, condition, inc, body)
       }
-      Stmt::If(ref condition, ref body) => self.handle_if(condition, body),
+      Stmt::While(ref condition, ref body) => self.handle_while(condition, body),
+      Stmt::ForIn(ref identifier, ref iterable, ref body) => {
+        self.handle_for_in(identifier, iterable, body)
+      }
+      Stmt::If(ref condition, ref body, ref else_body) => {
+        self.handle_if(condition, body, else_body)
+      }
+      Stmt::Import(ref path, ref alias) => self.handle_import(stmt, path, alias),
+      Stmt::Error => self.error(
+        ErrorKind::Syntax,
+        "Cannot run a statement with a syntax error".to_string(),
+        Some(stmt.span()),
+      ),
+      Stmt::Break => self.handle_loop_control(stmt, Flow::Break),
+      Stmt::Continue => self.handle_loop_control(stmt, Flow::Continue),
     }
   }
 
@@ -137,237 +718,1234 @@ impl Runtime {
     cond: &Meta<Expr>,
     inc: &Meta<Stmt>,
     body: &Meta<Stmt>,
-  ) -> Result<(), RuntimeError> {
+  ) -> Result<Flow, RuntimeError> {
     self.run_stmt(assign)?;
-    while get_number(self.run_expr(cond)?)? > 0.0 {
-      self.run_stmt(body)?;
-      self.run_stmt(inc)?;
+    self.loop_depth += 1;
+    let result = self.run_loop(cond, body, Some(inc));
+    self.loop_depth -= 1;
+    result
+  }
+
+  /// Runs an AST while loop
+  fn handle_while(&mut self, cond: &Meta<Expr>, body: &Meta<Stmt>) -> Result<Flow, RuntimeError> {
+    self.loop_depth += 1;
+    let result = self.run_loop(cond, body, None);
+    self.loop_depth -= 1;
+    result
+  }
+
+  /// Runs an AST for-in loop, binding each element of a list to the loop
+  /// variable in a scope of its own
+  fn handle_for_in(
+    &mut self,
+    identifier: &str,
+    iterable: &Meta<Expr>,
+    body: &Meta<Stmt>,
+  ) -> Result<Flow, RuntimeError> {
+    // ranges are looped over without making a list of their elements
+    let items: Box<dyn Iterator<Item = Object>> = match iterable.inside {
+      Expr::Range(ref start, ref end, ref step, inclusive) => {
+        Box::new(self.make_range(iterable, start, end, step, inclusive)?)
+      }
+      _ => match self.run_expr(iterable)? {
+        Object::List(items) => Box::new(items.into_iter()),
+        other => {
+          return self.error(
+            ErrorKind::Type,
+            format!("Cannot iterate over: {:?}", other),
+            Some(iterable.span()),
+          )
+        }
+      },
+    };
+    self.loop_depth += 1;
+    let mut result = Ok(Flow::Normal);
+    for item in items {
+      let mut scope = HashMap::new();
+      scope.insert(
+        identifier.to_string(),
+        SymbolEntry::Variable(VarEntry {
+          name: identifier.to_string(),
+          value: item,
+        }),
+      );
+      let scope = self.new_scope(scope);
+      self.symbol_table.push(scope);
+      let flow = self.run_stmt(body);
+      self.symbol_table.pop();
+      match flow {
+        Ok(Flow::Break) => break,
+        Ok(Flow::Normal) | Ok(Flow::Continue) => (),
+        Ok(flow @ Flow::Return(_)) => {
+          result = Ok(flow);
+          break;
+        }
+        Err(e) => {
+          result = Err(e);
+          break;
+        }
+      }
     }
-    Ok(())
+    self.loop_depth -= 1;
+    result
   }
 
-  /// Runs an AST if statement
-  fn handle_if(&mut self, cond: &Meta<Expr>, body: &Meta<Stmt>) -> Result<(), RuntimeError> {
-    if get_number(self.run_expr(cond)?)? > 0.0 {
-      self.run_stmt(body)?;
+  /// Repeatedly runs a loop body (followed by the increment statement, if
+  /// any) while the condition holds, handling break and continue
+  fn run_loop(
+    &mut self,
+    cond: &Meta<Expr>,
+    body: &Meta<Stmt>,
+    inc: Option<&Meta<Stmt>>,
+  ) -> Result<Flow, RuntimeError> {
+    while self.run_bool(cond)? {
+      match self.run_stmt(body)? {
+        Flow::Break => break,
+        Flow::Normal | Flow::Continue => (),
+        flow @ Flow::Return(_) => return Ok(flow),
+      }
+      if let Some(inc) = inc {
+        self.run_stmt(inc)?;
+      }
     }
-    Ok(())
+    Ok(Flow::Normal)
   }
 
-  /// Processes an AST block, running any statements within
-  fn handle_block(&mut self, stmts: &Vec<Meta<Stmt>>) -> Result<(), RuntimeError> {
-    self.symbol_table.push(HashMap::new());
-    for stmt in stmts {
-      self.run_stmt(&stmt)?;
+  /// Runs an AST break or continue statement, which is only valid inside
+  /// a loop of the current function
+  fn handle_loop_control(&mut self, stmt: &Meta<Stmt>, flow: Flow) -> Result<Flow, RuntimeError> {
+    if self.loop_depth == 0 {
+      let keyword = if flow == Flow::Break {
+        "break"
+      } else {
+        "continue"
+      };
+      return self.error(
+        ErrorKind::Control,
+        format!("`{}` used outside of a loop", keyword),
+        Some(stmt.span()),
+      );
     }
-    self.symbol_table.pop();
-    Ok(())
+    Ok(flow)
   }
 
-  /// Runs an AST return statement, inserting the value into the symbol table
-  /// as the function return
-  fn handle_return(&mut self, expr: &Meta<Expr>) -> Result<(), RuntimeError> {
-    let return_val = self.run_expr(expr)?;
+  /// Runs an AST if statement, along with its else branch if present
+  fn handle_if(
+    &mut self,
+    cond: &Meta<Expr>,
+    body: &Meta<Stmt>,
+    else_body: &Option<Box<Meta<Stmt>>>,
+  ) -> Result<Flow, RuntimeError> {
+    if self.run_bool(cond)? {
+      self.run_stmt(body)
+    } else if let Some(else_body) = else_body {
+      self.run_stmt(else_body)
+    } else {
+      Ok(Flow::Normal)
+    }
+  }
 
-    // insert return val in closest function call
-    for table in self.symbol_table.iter_mut().rev() {
-      if let Some(SymbolEntry::Function(_)) = table.get(CURRENT_FUNCTION_CALL_KEY) {
-        table.insert(
-          CURRENT_FUNCTION_CALL_KEY.to_string(),
-          SymbolEntry::Function(FunctionMetadataEntry {
-            return_val: Some(return_val),
-          }),
+  /// Goes one level deeper into the program, failing if that is deeper than
+  /// the nesting limit allows. Each successful call must be matched by
+  /// decrementing `nesting` on the way out.
+  fn enter(&mut self, span: Span) -> Result<(), RuntimeError> {
+    if let Some(max) = self.limits.max_nesting {
+      if self.nesting >= max {
+        return self.error(
+          ErrorKind::Limit,
+          format!("Maximum nesting depth of {} exceeded", max),
+          Some(span),
         );
-        break;
       }
     }
+    self.nesting += 1;
     Ok(())
   }
 
-  /// Runs an AST expr statement
-  fn handle_expr(&mut self, expr: &Meta<Expr>) -> Result<(), RuntimeError> {
-    self.run_expr(&expr)?;
+  /// Counts a step against the limits on steps taken and time taken,
+  /// failing if either is used up
+  fn step(&mut self, span: Span) -> Result<(), RuntimeError> {
+    self.steps_taken += 1;
+    if let Some(max) = self.limits.max_steps {
+      if self.steps_taken > max {
+        return self.error(
+          ErrorKind::Limit,
+          format!("Limit of {} steps exceeded", max),
+          Some(span),
+        );
+      }
+    }
+    if let Some(max) = self.limits.max_time {
+      if self.started.elapsed() > max {
+        return self.error(
+          ErrorKind::Limit,
+          format!("Time limit of {:?} exceeded", max),
+          Some(span),
+        );
+      }
+    }
     Ok(())
   }
 
-  /// Processes an AST assignment statement
-  fn handle_assign(&mut self, identifier: String, expr: &Meta<Expr>) -> Result<(), RuntimeError> {
-    let val = self.run_expr(&expr)?;
-    if let Some(table_for_scope) = self.symbol_table.last_mut() {
-      table_for_scope.insert(
-        identifier.clone(),
-        SymbolEntry::Variable(VarEntry {
-          name: identifier.clone(),
-          value: SymbolVal::Object(val),
-        }),
-      );
+  /// Processes an AST block, running any statements within. Stops early if a
+  /// statement transfers control out of the block.
+  fn handle_block(&mut self, stmts: &Vec<Meta<Stmt>>) -> Result<Flow, RuntimeError> {
+    let scope = self.new_scope(HashMap::new());
+    self.symbol_table.push(scope);
+    let mut flow = Flow::Normal;
+    for stmt in stmts {
+      flow = self.run_stmt(&stmt)?;
+      if flow != Flow::Normal {
+        break;
+      }
     }
-    Ok(())
+    self.symbol_table.pop();
+    Ok(flow)
   }
 
-  /// Processes an AST function declaration
-  fn handle_function_declaration(
+  /// Runs an AST return statement, which unwinds to the enclosing function
+  /// call
+  fn handle_return(
     &mut self,
-    identifier: String,
-    params: &Vec<String>,
     stmt: &Meta<Stmt>,
-  ) -> Result<(), RuntimeError> {
-    if let Some(table_for_scope) = self.symbol_table.last_mut() {
-      table_for_scope.insert(
-        identifier.clone(),
-        SymbolEntry::Variable(VarEntry {
-          name: identifier.clone(),
-          value: SymbolVal::Function(params.clone(), stmt.clone()),
-        }),
+    expr: &Option<Meta<Expr>>,
+  ) -> Result<Flow, RuntimeError> {
+    let in_function = self
+      .symbol_table
+      .iter()
+      .any(|table| table.borrow().contains_key(CURRENT_FUNCTION_CALL_KEY));
+    if !in_function {
+      return self.error(
+        ErrorKind::Control,
+        "`return` used outside of a function".to_string(),
+        Some(stmt.span()),
       );
     }
-    Ok(())
+    let return_val = match expr {
+      Some(expr) => self.run_expr(expr)?,
+      None => Object::None,
+    };
+    Ok(Flow::Return(return_val))
   }
 
-  /// Runs an AST expr
-  fn run_expr(&mut self, expr: &Meta<Expr>) -> Result<Object, RuntimeError> {
-    match expr.inside {
-      Expr::Binary(ref op, ref e1, ref e2) => self.handle_binary(op, e1, e2),
-      Expr::Unary(ref op, ref e1) => self.handle_unary(op, e1),
-      Expr::FunctionCall(ref name, ref args) => {
-        self.handle_function_call(expr, name.to_string(), args)
-      }
-      Expr::Identifier(ref name) => self.handle_identifier(expr, name),
-      Expr::Number(num) => self.handle_number(Object::Number(num)),
-      Expr::Str(ref s) => self.handle_str(Object::Str(s.clone())),
-      Expr::List(ref l) => self.handle_list(l),
-    }
+  /// Runs an AST expr statement
+  fn handle_expr(&mut self, expr: &Meta<Expr>) -> Result<Flow, RuntimeError> {
+    self.run_expr(&expr)?;
+    Ok(Flow::Normal)
   }
 
-  /// Processes an AST binary operator
-  fn handle_binary(
-    &mut self,
-    operator: &Operator,
-    expr1: &Meta<Expr>,
-    expr2: &Meta<Expr>,
-  ) -> Result<Object, RuntimeError> {
-    let e1_num = get_number(self.run_expr(&expr1)?)?;
-    let e2_num = get_number(self.run_expr(&expr2)?)?;
-    let result = match operator {
-      Operator::Multiply => e1_num * e2_num,
-      Operator::Divide => e1_num / e2_num,
-      Operator::Add => e1_num + e2_num,
-      Operator::Subtract => e1_num - e2_num,
-      Operator::Mod => e1_num % e2_num,
-      _ => 0.0, // TODO: error
-    };
-    Ok(Object::Number(result))
+  /// Processes an AST assignment statement
+  fn handle_assign(&mut self, identifier: String, expr: &Meta<Expr>) -> Result<Flow, RuntimeError> {
+    let val = self.run_expr(&expr)?;
+    self.assign_var(identifier, val);
+    Ok(Flow::Normal)
   }
 
-  /// Processes an AST unary operator
-  fn handle_unary(
+  /// Processes an AST destructuring assignment, which unpacks a list into
+  /// one variable per element
+  fn handle_destructure(
     &mut self,
-    operator: &Operator,
-    expr1: &Meta<Expr>,
-  ) -> Result<Object, RuntimeError> {
-    let e1_num = get_number(self.run_expr(&expr1)?)?;
-    let result = match operator {
-      Operator::Negate => -e1_num,
-      _ => 0.0, // TODO: error
+    identifiers: &Vec<String>,
+    expr: &Meta<Expr>,
+  ) -> Result<Flow, RuntimeError> {
+    let items = match self.run_expr(&expr)? {
+      Object::List(ref items) if items.len() == identifiers.len() => items.clone(),
+      Object::List(items) => {
+        return self.error(
+          ErrorKind::Value,
+          format!(
+            "Cannot unpack a list of length {} into {} variables",
+            items.len(),
+            identifiers.len()
+          ),
+          Some(expr.span()),
+        )
+      }
+      other => {
+        return self.error(
+          ErrorKind::Type,
+          format!("Cannot unpack {:?}, expected a list", other),
+          Some(expr.span()),
+        )
+      }
     };
-    Ok(Object::Number(result))
+    for (identifier, val) in identifiers.iter().zip(items) {
+      self.assign_var(identifier.clone(), val);
+    }
+    Ok(Flow::Normal)
   }
 
-  /// Processes and runs a function call (may be a stdlib function call
-  /// or a function defined in source code)
-  fn handle_function_call(
-    &mut self,
-    expr: &Meta<Expr>,
-    identifier: String,
-    exprs: &Vec<Meta<Expr>>,
-  ) -> Result<Object, RuntimeError> {
-    if let Some(var) = get_var(&identifier, &self.symbol_table.clone()) {
-      match var.value {
-        SymbolVal::Function(ref params, ref stmt) => {
-          self.handle_language_function_call(expr, exprs, params, stmt)
-        }
-        SymbolVal::StdLib(ref name) => {
-          let mut evaled_args = vec![];
-          for expr in exprs {
-            evaled_args.push(self.run_expr(expr)?);
-          }
-          self.run_stdlib_function_call(name, evaled_args)
-        }
-        SymbolVal::Object(..) => self.error(
-          format!("Object is not a function: {:?}", identifier),
-          Some(expr.byte_offset),
-        ),
+  /// Sets a variable. Assigning to a variable that is already defined in the
+  /// current function updates it in place; otherwise a new variable is
+  /// created in the innermost scope.
+  fn assign_var(&mut self, identifier: String, val: Object) {
+    let entry = SymbolEntry::Variable(VarEntry {
+      name: identifier.clone(),
+      value: val,
+    });
+    for table in self.symbol_table.iter().rev() {
+      let mut table = table.borrow_mut();
+      if let Some(SymbolEntry::Variable(_)) = table.get(&identifier) {
+        table.insert(identifier, entry);
+        return;
       }
-    } else {
-      self.error(
-        format!("Couldn't find function with name: {}", identifier),
-        Some(expr.byte_offset),
-      )
+      if table.contains_key(CURRENT_FUNCTION_CALL_KEY) {
+        break;
+      }
+    }
+    if let Some(table_for_scope) = self.symbol_table.last() {
+      table_for_scope.borrow_mut().insert(identifier, entry);
     }
   }
 
-  /// Runs a function defined in code (as opposed to stdlib) and returns
-  /// the result as an Object
-  fn handle_language_function_call(
+  /// Runs an AST import statement. The module is run once in a namespace of
+  /// its own; its top-level variables become the fields of a map that is
+  /// bound to the alias, or to the file name if there is none.
+  fn handle_import(
     &mut self,
-    call_expr: &Meta<Expr>,
-    exprs: &Vec<Meta<Expr>>,
-    params: &Vec<String>,
     stmt: &Meta<Stmt>,
-  ) -> Result<Object, RuntimeError> {
-    if exprs.len() != params.len() {
+    path: &str,
+    alias: &Option<String>,
+  ) -> Result<Flow, RuntimeError> {
+    let name = match alias.clone().or_else(|| module_name(path)) {
+      Some(name) => name,
+      None => {
+        return self.error(
+          ErrorKind::Import,
+          format!(
+            "Module `{}` needs a name, e.g. `import \"{}\" as name;`",
+            path, path
+          ),
+          Some(stmt.span()),
+        )
+      }
+    };
+    let relative = Path::new(path)
+      .components()
+      .all(|component| match component {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+      });
+    if !relative {
       return self.error(
-        format!("Number of expr args doesn't match number of params"),
-        Some(call_expr.byte_offset),
+        ErrorKind::Import,
+        format!("Module path `{}` must be relative and can't use `..`", path),
+        Some(stmt.span()),
       );
     }
+    let resolved = match self.resolve_import(path) {
+      Some(resolved) => resolved,
+      None => {
+        return self.error(
+          ErrorKind::Import,
+          format!("Couldn't find module `{}`", path),
+          Some(stmt.span()),
+        )
+      }
+    };
+    if let Some(start) = self.import_stack.iter().position(|p| *p == resolved) {
+      let cycle: Vec<String> = self.import_stack[start..]
+        .iter()
+        .chain(Some(&resolved))
+        .map(|p| p.display().to_string())
+        .collect();
+      return self.error(
+        ErrorKind::Import,
+        format!("Import cycle: {}", cycle.join(" -> ")),
+        Some(stmt.span()),
+      );
+    }
+    let module = match self.modules.get(&resolved) {
+      Some(module) => module.clone(),
+      None => {
+        let module = self.load_module(stmt, &resolved)?;
+        self.modules.insert(resolved, module.clone());
+        module
+      }
+    };
+    self.assign_var(name, module);
+    Ok(Flow::Normal)
+  }
+
+  /// Finds an imported file, looking next to the importing file (or in the
+  /// working directory if it wasn't read from a file) and then in each
+  /// library path. Files outside of those directories, e.g. through a
+  /// symlink, aren't found.
+  fn resolve_import(&self, path: &str) -> Option<PathBuf> {
+    let base = match self.source.path {
+      Some(ref file) => file.parent().unwrap_or(Path::new(".")).to_path_buf(),
+      None => PathBuf::from("."),
+    };
+    Some(base)
+      .into_iter()
+      .chain(self.library_paths.iter().cloned())
+      .filter_map(|dir| {
+        let dir = dir.canonicalize().ok()?;
+        let found = dir.join(path).canonicalize().ok()?;
+        if found.starts_with(&dir) && found.is_file() {
+          Some(found)
+        } else {
+          None
+        }
+      })
+      .next()
+  }
+
+  /// Reads, parses, type checks and runs a module, returning its namespace
+  fn load_module(&mut self, stmt: &Meta<Stmt>, path: &Path) -> Result<Object, RuntimeError> {
+    let code = match fs::read_to_string(path) {
+      Ok(code) => code,
+      Err(e) => {
+        return self.error(
+          ErrorKind::Import,
+          format!("Couldn't read module {}: {}", path.display(), e),
+          Some(stmt.span()),
+        )
+      }
+    };
+    let module_source = Rc::new(Source {
+      code: code,
+      path: Some(path.to_path_buf()),
+    });
+    // the module's own errors are noted where they are, so the importer
+    // can report them along with the import
+    let note = |span: Span, msg: &str| {
+      (
+        Location {
+          source: module_source.clone(),
+          span: span,
+        },
+        msg.to_string(),
+      )
+    };
+    let program = match parse_program(&module_source.code) {
+      Ok(program) => program,
+      Err(errors) => {
+        let mut error = RuntimeError::new(
+          ErrorKind::Syntax,
+          format!("Couldn't parse module {}", path.display()),
+        );
+        error.location = Some(self.location(stmt.span()));
+        error.notes = errors.iter().map(|e| note(e.span(), &e.msg)).collect();
+        return Err(error);
+      }
+    };
+    // modules are checked the same way main checks the program it runs,
+    // starting from the depth of the import
+    let type_errors = typecheck::check_program(&program, self.nesting, self.limits.max_nesting);
+    if !type_errors.is_empty() {
+      // the module may be nested too deeply to drop without overflowing the
+      // stack, so it's leaked instead
+      mem::forget(program);
+      let mut error = RuntimeError::new(
+        ErrorKind::Type,
+        format!("Module {} has type errors", path.display()),
+      );
+      error.location = Some(self.location(stmt.span()));
+      error.notes = type_errors.iter().map(|e| note(e.span, &e.msg)).collect();
+      return Err(error);
+    }
+
+    let importer_source = mem::replace(&mut self.source, module_source);
+    let module_scope = self.new_scope(HashMap::new());
+    let importer_scopes = mem::replace(&mut self.symbol_table, vec![module_scope]);
+    let loop_depth = mem::replace(&mut self.loop_depth, 0);
+    self.import_stack.push(path.to_path_buf());
+    self.add_stdlib();
+
+    let result = self.run_module(&program);
+
+    self.import_stack.pop();
+    self.loop_depth = loop_depth;
+    self.symbol_table = importer_scopes;
+    self.source = importer_source;
+    result
+  }
+
+  /// Runs the statements of a module, which must be the only scope in the
+  /// symbol table, and collects the variables it defines
+  fn run_module(&mut self, program: &Vec<Meta<Stmt>>) -> Result<Object, RuntimeError> {
+    for stmt in program {
+      self.run_stmt(stmt)?;
+    }
+    let builtins: HashMap<String, Object> = builtins().into_iter().collect();
+    let mut namespace = BTreeMap::new();
+    for (name, entry) in self.symbol_table[0].borrow().iter() {
+      if let SymbolEntry::Variable(var) = entry {
+        if builtins.get(name) != Some(&var.value) {
+          namespace.insert(name.clone(), var.value.clone());
+        }
+      }
+    }
+    Ok(Object::Map(namespace))
+  }
+
+  /// Processes an AST function declaration, which defines a variable in the
+  /// innermost scope holding the function
+  fn handle_function_declaration(
+    &mut self,
+    identifier: String,
+    params: &Vec<Param>,
+    ret: &Option<Type>,
+    body: &Meta<Stmt>,
+    span: Span,
+  ) -> Result<Flow, RuntimeError> {
+    let function = self.make_closure(Some(identifier.clone()), params, ret, body, span);
+    if let Some(table_for_scope) = self.symbol_table.last() {
+      table_for_scope.borrow_mut().insert(
+        identifier.clone(),
+        SymbolEntry::Variable(VarEntry {
+          name: identifier,
+          value: function,
+        }),
+      );
+    }
+    Ok(Flow::Normal)
+  }
+
+  /// Creates a function that closes over the current scopes. The function
+  /// sees later changes to variables in those scopes, which lets it call
+  /// itself and functions declared after it.
+  fn make_closure(
+    &self,
+    name: Option<String>,
+    params: &Vec<Param>,
+    ret: &Option<Type>,
+    body: &Meta<Stmt>,
+    span: Span,
+  ) -> Object {
+    Object::Function(Function::Closure(Rc::new(Closure {
+      name: name,
+      params: params.clone(),
+      ret: ret.clone(),
+      body: body.clone(),
+      env: self.symbol_table.clone(),
+      source: self.source.clone(),
+      span: span,
+    })))
+  }
+
+  /// Runs an AST expr
+  fn run_expr(&mut self, expr: &Meta<Expr>) -> Result<Object, RuntimeError> {
+    self.step(expr.span())?;
+    self.enter(expr.span())?;
+    let result = self.dispatch_expr(expr);
+    self.nesting -= 1;
+    result
+  }
+
+  fn dispatch_expr(&mut self, expr: &Meta<Expr>) -> Result<Object, RuntimeError> {
+    match expr.inside {
+      Expr::Binary(ref op, ref e1, ref e2) => self.handle_binary(op, e1, e2),
+      Expr::Unary(ref op, ref e1) => self.handle_unary(op, e1),
+      Expr::FunctionCall(ref name, ref args) => {
+        self.handle_function_call(expr, name.to_string(), args)
+      }
+      Expr::MethodCall(ref receiver, ref name, ref args) => {
+        self.handle_method_call(expr, receiver, name, args)
+      }
+      Expr::Member(ref object, ref name) => self.handle_member(expr, object, name),
+      Expr::Index(ref object, ref index) => self.handle_index(expr, object, index),
+      Expr::Slice(ref object, ref start, ref end) => self.handle_slice(expr, object, start, end),
+      Expr::Identifier(ref name) => self.handle_identifier(expr, name),
+      Expr::Number(num) => self.handle_number(Object::Number(num)),
+      Expr::Quantity(num, unit) => {
+        self.handle_number(make_quantity(unit.to_base(num), unit.dimension()))
+      }
+      Expr::Function(ref params, ref ret, ref body) => {
+        Ok(self.make_closure(None, params, ret, body, expr.span()))
+      }
+      Expr::Vector(num, axis) => Ok(Object::Vector(axis.components(num).into())),
+      Expr::Bool(b) => Ok(Object::Bool(b)),
+      Expr::Str(ref s) => self.handle_str(Object::Str(s.clone())),
+      Expr::Interpolate(ref parts) => self.handle_interpolate(parts),
+      Expr::List(ref l) => self.handle_list(l),
+      Expr::Map(ref entries) => self.handle_map(entries),
+      Expr::Range(ref start, ref end, ref step, inclusive) => {
+        self.handle_range(expr, start, end, step, inclusive)
+      }
+    }
+  }
+
+  /// Processes an AST binary operator
+  fn handle_binary(
+    &mut self,
+    operator: &Operator,
+    expr1: &Meta<Expr>,
+    expr2: &Meta<Expr>,
+  ) -> Result<Object, RuntimeError> {
+    match operator {
+      Operator::And | Operator::Or => return self.handle_logical(operator, expr1, expr2),
+      _ => (),
+    }
+    let e1 = self.run_expr(&expr1)?;
+    let e2 = self.run_expr(&expr2)?;
+    match operator {
+      Operator::Equal
+      | Operator::NotEqual
+      | Operator::Less
+      | Operator::LessEqual
+      | Operator::Greater
+      | Operator::GreaterEqual => return self.handle_comparison(operator, expr1, e1, e2),
+      _ => (),
+    }
+    if let (Object::Solid(s1), Object::Solid(s2)) = (&e1, &e2) {
+      return self.handle_csg(operator, expr1, s1, s2);
+    }
+    if is_geometric(&e1) || is_geometric(&e2) {
+      return self.handle_vector_arithmetic(operator, expr1, &e1, &e2);
+    }
+    match (operator, &e1, &e2) {
+      (Operator::Add, Object::Str(s1), Object::Str(s2)) => {
+        return Ok(Object::Str(format!("{}{}", s1, s2)))
+      }
+      (_, Object::Str(_), _) | (_, _, Object::Str(_)) => {
+        return self.error(
+          ErrorKind::Type,
+          format!(
+            "Operator {:?} is not defined for {} and {}",
+            operator,
+            e1.type_name(),
+            e2.type_name()
+          ),
+          Some(expr1.span()),
+        )
+      }
+      _ => (),
+    }
+    match operator {
+      Operator::Union | Operator::Intersection => {
+        return self.error(
+          ErrorKind::Type,
+          format!(
+            "Cannot combine {} and {}, `|` and `&` only apply to solids",
+            e1.type_name(),
+            e2.type_name()
+          ),
+          Some(expr1.span()),
+        )
+      }
+      _ => (),
+    }
+    let (e1_num, e1_dim) = match get_quantity(e1) {
+      Ok(quantity) => quantity,
+      Err(e) => return self.locate(e, expr1.span()),
+    };
+    let (e2_num, e2_dim) = match get_quantity(e2) {
+      Ok(quantity) => quantity,
+      Err(e) => return self.locate(e, expr2.span()),
+    };
+    let (result, dim) = match operator {
+      Operator::Multiply => (e1_num * e2_num, e1_dim.mul(e2_dim)),
+      Operator::Divide => (e1_num / e2_num, e1_dim.div(e2_dim)),
+      Operator::Add => (
+        e1_num + e2_num,
+        self.common_dimension("add", expr1, e1_dim, e2_dim)?,
+      ),
+      Operator::Subtract => (
+        e1_num - e2_num,
+        self.common_dimension("subtract", expr1, e1_dim, e2_dim)?,
+      ),
+      Operator::Mod => (
+        e1_num % e2_num,
+        self.common_dimension("take the remainder of", expr1, e1_dim, e2_dim)?,
+      ),
+      other => {
+        return self.error(
+          ErrorKind::Type,
+          format!("Operator {:?} is not defined for numbers", other),
+          Some(expr1.span()),
+        )
+      }
+    };
+    Ok(make_quantity(result, dim))
+  }
+
+  /// Processes a binary operator between two solids: `+` and `|` give the
+  /// union, `-` the difference and `&` the intersection
+  fn handle_csg(
+    &self,
+    operator: &Operator,
+    expr: &Meta<Expr>,
+    s1: &Solid,
+    s2: &Solid,
+  ) -> Result<Object, RuntimeError> {
+    let op = match operator {
+      Operator::Add | Operator::Union => Boolean::Union,
+      Operator::Subtract => Boolean::Difference,
+      Operator::Intersection => Boolean::Intersection,
+      _ => {
+        return self.error(
+          ErrorKind::Type,
+          format!("Operator {:?} is not defined for solids", operator),
+          Some(expr.span()),
+        )
+      }
+    };
+    Ok(Object::Solid(boolean(s1, s2, op)))
+  }
+
+  /// Processes a binary operator involving vectors or points. Vectors can be
+  /// added, subtracted and scaled by plain numbers or lengths; a vector can
+  /// move a point, and the difference of two points is a vector. Vectors are
+  /// measured in millimeters, so `3mm * Z` and `3 * Z` are the same.
+  fn handle_vector_arithmetic(
+    &self,
+    operator: &Operator,
+    expr: &Meta<Expr>,
+    e1: &Object,
+    e2: &Object,
+  ) -> Result<Object, RuntimeError> {
+    let result = match (operator, e1, e2) {
+      (Operator::Add, Object::Vector(a), Object::Vector(b)) => Some(Object::Vector(*a + *b)),
+      (Operator::Subtract, Object::Vector(a), Object::Vector(b)) => Some(Object::Vector(*a - *b)),
+      (Operator::Add, Object::Point(p), Object::Vector(v))
+      | (Operator::Add, Object::Vector(v), Object::Point(p)) => {
+        Some(Object::Point(Point { pos: p.pos + *v }))
+      }
+      (Operator::Subtract, Object::Point(p), Object::Vector(v)) => {
+        Some(Object::Point(Point { pos: p.pos - *v }))
+      }
+      (Operator::Subtract, Object::Point(a), Object::Point(b)) => Some(Object::Vector(*a - *b)),
+      (Operator::Multiply, Object::Vector(v), n) | (Operator::Multiply, n, Object::Vector(v)) => {
+        scale(n).map(|n| Object::Vector(*v * n))
+      }
+      (Operator::Divide, Object::Vector(v), n) => scale(n).map(|n| Object::Vector(*v * (1.0 / n))),
+      _ => None,
+    };
+    match result {
+      Some(result) => Ok(result),
+      None => self.error(
+        ErrorKind::Type,
+        format!(
+          "Operator {:?} is not defined for {} and {}",
+          operator,
+          e1.type_name(),
+          e2.type_name()
+        ),
+        Some(expr.span()),
+      ),
+    }
+  }
+
+  /// Finds the dimension of the result of combining two quantities that must
+  /// share a dimension. Plain numbers take on the dimension of the other side.
+  fn common_dimension(
+    &self,
+    action: &str,
+    expr: &Meta<Expr>,
+    d1: Dimension,
+    d2: Dimension,
+  ) -> Result<Dimension, RuntimeError> {
+    if d1 == d2 || d2.is_none() {
+      Ok(d1)
+    } else if d1.is_none() {
+      Ok(d2)
+    } else {
+      self.error(
+        ErrorKind::Type,
+        format!("Cannot {} quantities in {} and {}", action, d1, d2),
+        Some(expr.span()),
+      )
+    }
+  }
+
+  /// Processes a short-circuiting `&&` or `||`
+  fn handle_logical(
+    &mut self,
+    operator: &Operator,
+    expr1: &Meta<Expr>,
+    expr2: &Meta<Expr>,
+  ) -> Result<Object, RuntimeError> {
+    let lhs = self.run_bool(expr1)?;
+    let result = match operator {
+      Operator::And => lhs && self.run_bool(expr2)?,
+      _ => lhs || self.run_bool(expr2)?,
+    };
+    Ok(Object::Bool(result))
+  }
+
+  /// Processes a comparison. Numbers (and quantities of the same dimension)
+  /// and strings can be ordered; anything else can only be tested for equality.
+  fn handle_comparison(
+    &mut self,
+    operator: &Operator,
+    expr: &Meta<Expr>,
+    e1: Object,
+    e2: Object,
+  ) -> Result<Object, RuntimeError> {
+    let ordering = match (&e1, &e2) {
+      _ if is_numeric(&e1) && is_numeric(&e2) => {
+        let (a, a_dim) = get_quantity(e1.clone())?;
+        let (b, b_dim) = get_quantity(e2.clone())?;
+        self.common_dimension("compare", expr, a_dim, b_dim)?;
+        a.partial_cmp(&b)
+      }
+      (Object::Str(a), Object::Str(b)) => Some(a.cmp(b)),
+      _ => match operator {
+        Operator::Equal => return Ok(Object::Bool(e1 == e2)),
+        Operator::NotEqual => return Ok(Object::Bool(e1 != e2)),
+        _ => {
+          return self.error(
+            ErrorKind::Type,
+            format!("Cannot compare {:?} with {:?}", e1, e2),
+            Some(expr.span()),
+          )
+        }
+      },
+    };
+    let result = match ordering {
+      Some(ordering) => match operator {
+        Operator::Equal => ordering == Ordering::Equal,
+        Operator::NotEqual => ordering != Ordering::Equal,
+        Operator::Less => ordering == Ordering::Less,
+        Operator::LessEqual => ordering != Ordering::Greater,
+        Operator::Greater => ordering == Ordering::Greater,
+        _ => ordering != Ordering::Less,
+      },
+      // NaN compares unequal to everything
+      None => *operator == Operator::NotEqual,
+    };
+    Ok(Object::Bool(result))
+  }
+
+  /// Evaluates a condition or logical operand, which must be a boolean
+  fn run_bool(&mut self, expr: &Meta<Expr>) -> Result<bool, RuntimeError> {
+    match self.run_expr(expr)? {
+      Object::Bool(b) => Ok(b),
+      other => self.error(
+        ErrorKind::Type,
+        format!("Expected a boolean condition, got: {:?}", other),
+        Some(expr.span()),
+      ),
+    }
+  }
+
+  /// Processes an AST unary operator
+  fn handle_unary(
+    &mut self,
+    operator: &Operator,
+    expr1: &Meta<Expr>,
+  ) -> Result<Object, RuntimeError> {
+    if let Operator::Not = operator {
+      return Ok(Object::Bool(!self.run_bool(expr1)?));
+    }
+    let e1 = self.run_expr(&expr1)?;
+    if let (Operator::Negate, Object::Vector(v)) = (operator, &e1) {
+      return Ok(Object::Vector(-*v));
+    }
+    let (e1_num, dim) = match get_quantity(e1) {
+      Ok(quantity) => quantity,
+      Err(e) => return self.locate(e, expr1.span()),
+    };
+    let result = match operator {
+      Operator::Negate => -e1_num,
+      other => {
+        return self.error(
+          ErrorKind::Type,
+          format!("Operator {:?} can't be applied to a single number", other),
+          Some(expr1.span()),
+        )
+      }
+    };
+    Ok(make_quantity(result, dim))
+  }
+
+  /// Processes and runs a function call (may be a stdlib function call
+  /// or a function defined in source code)
+  fn handle_function_call(
+    &mut self,
+    expr: &Meta<Expr>,
+    identifier: String,
+    arg_exprs: &Vec<Argument>,
+  ) -> Result<Object, RuntimeError> {
+    let args = self.run_args(arg_exprs)?;
+    self.call_function(expr, &identifier, args)
+  }
+
+  /// Processes an AST method call `receiver.name(args)`, which calls the
+  /// function `name` with the receiver as the first argument. If the
+  /// receiver is a map (such as an imported module) with a function in the
+  /// field `name`, that function is called with just the arguments instead.
+  fn handle_method_call(
+    &mut self,
+    expr: &Meta<Expr>,
+    receiver: &Meta<Expr>,
+    name: &str,
+    arg_exprs: &Vec<Argument>,
+  ) -> Result<Object, RuntimeError> {
+    let receiver = self.run_expr(receiver)?;
+    let mut args = self.run_args(arg_exprs)?;
+    if let Object::Map(ref fields) = receiver {
+      if let Some(Object::Function(function)) = fields.get(name) {
+        return self.call(expr, function, args);
+      }
+    }
+    args.positional.insert(0, receiver);
+    match get_method(name, &self.symbol_table) {
+      Some(function) => self.call(expr, &function, args),
+      None => self.error(
+        ErrorKind::Name,
+        format!("Couldn't find a method named `{}`", name),
+        Some(expr.span()),
+      ),
+    }
+  }
+
+  /// Looks up a function by name and calls it with already evaluated
+  /// arguments
+  fn call_function(
+    &mut self,
+    expr: &Meta<Expr>,
+    identifier: &str,
+    args: Args,
+  ) -> Result<Object, RuntimeError> {
+    match get_var(identifier, &self.symbol_table) {
+      Some(Object::Function(function)) => self.call(expr, &function, args),
+      Some(_) => self.error(
+        ErrorKind::Type,
+        format!("Object is not a function: {:?}", identifier),
+        Some(expr.span()),
+      ),
+      None => self.error(
+        ErrorKind::Name,
+        format!("Couldn't find function with name: {}", identifier),
+        Some(expr.span()),
+      ),
+    }
+  }
+
+  /// Calls a function value with already evaluated arguments
+  fn call(
+    &mut self,
+    expr: &Meta<Expr>,
+    function: &Function,
+    args: Args,
+  ) -> Result<Object, RuntimeError> {
+    match function {
+      Function::Closure(closure) => self.handle_language_function_call(expr, closure, args),
+      Function::StdLib(name) => {
+        match self.run_stdlib_function_call(name, args) {
+          // stdlib functions don't know where they were called from
+          Err(e) => self.locate(e, expr.span()),
+          result => result,
+        }
+      }
+    }
+  }
+
+  /// Processes an AST member access `object.name`
+  fn handle_member(
+    &mut self,
+    expr: &Meta<Expr>,
+    object: &Meta<Expr>,
+    name: &str,
+  ) -> Result<Object, RuntimeError> {
+    let object = self.run_expr(object)?;
+    match stdlib::get_property(&object, name) {
+      Err(e) => self.locate(e, expr.span()),
+      result => result,
+    }
+  }
+
+  /// Evaluates the arguments of a function call in the caller's scope
+  fn run_args(&mut self, arg_exprs: &Vec<Argument>) -> Result<Args, RuntimeError> {
+    let mut args = Args {
+      positional: vec![],
+      named: vec![],
+    };
+    for arg in arg_exprs {
+      match arg {
+        Argument::Positional(expr) => {
+          if !args.named.is_empty() {
+            return self.error(
+              ErrorKind::Argument,
+              "Positional argument follows keyword argument".to_string(),
+              Some(expr.span()),
+            );
+          }
+          args.positional.push(self.run_expr(expr)?);
+        }
+        Argument::Named(name, expr) => {
+          let val = self.run_expr(expr)?;
+          args.named.push((name.clone(), val));
+        }
+      }
+    }
+    Ok(args)
+  }
+
+  /// Runs a function defined in code (as opposed to stdlib) and returns
+  /// the result as an Object. The body runs in the scopes the function was
+  /// defined in rather than the caller's. Parameters that weren't given an
+  /// argument take their default values, which are evaluated at call time
+  /// after the preceding parameters have been bound.
+  fn handle_language_function_call(
+    &mut self,
+    call_expr: &Meta<Expr>,
+    closure: &Closure,
+    args: Args,
+  ) -> Result<Object, RuntimeError> {
+    let name = closure.name.as_ref().map_or("<anonymous>", |n| n.as_str());
+    let names: Vec<&str> = closure
+      .params
+      .iter()
+      .map(|param| param.name.as_str())
+      .collect();
+    let bound = match args.bind(name, &names) {
+      Ok(bound) => bound,
+      Err(e) => return Err(self.call_error(e, call_expr, closure)),
+    };
+    // parameters with defaults are bound later, so check for missing ones
+    // while the caller's source is still current
+    let missing = closure
+      .params
+      .iter()
+      .find(|param| param.default.is_none() && !bound.values.contains_key(&param.name));
+    if let Some(param) = missing {
+      let e = RuntimeError::new(
+        ErrorKind::Argument,
+        format!("{} is missing argument `{}`", name, param.name),
+      );
+      return Err(self.call_error(e, call_expr, closure));
+    }
+    // annotations are checked statically within a file, but calls into
+    // imported modules can only be checked here
+    for param in &closure.params {
+      if let (Some(ty), Some(value)) = (&param.ty, bound.values.get(&param.name)) {
+        let found = value.annotation_type();
+        if !ty.accepts(&found) {
+          let e = RuntimeError::new(
+            ErrorKind::Type,
+            format!(
+              "Argument `{}` of `{}` should be {}, found {}",
+              param.name, name, ty, found
+            ),
+          );
+          return Err(self.call_error(e, call_expr, closure));
+        }
+      }
+    }
+
+    if let Some(max) = self.limits.max_call_depth {
+      if self.call_depth >= max {
+        return self.error(
+          ErrorKind::Limit,
+          format!("Maximum call depth of {} exceeded", max),
+          Some(call_expr.span()),
+        );
+      }
+    }
+
+    // a call takes more of the stack than other levels, so it counts as one
+    // of its own
+    self.enter(call_expr.span())?;
 
     // add new scope level for function call
     let mut symbol_entry = HashMap::new();
     symbol_entry.insert(
       CURRENT_FUNCTION_CALL_KEY.to_string(),
-      SymbolEntry::Function(FunctionMetadataEntry {
-        return_val: Some(Object::Number(3.14)),
-      }),
+      SymbolEntry::Function(FunctionMetadataEntry {}),
     );
-    self.symbol_table.push(symbol_entry);
+    let caller_scopes = mem::replace(&mut self.symbol_table, closure.env.clone());
+    let caller_source = mem::replace(&mut self.source, closure.source.clone());
+    let frame = self.new_scope(symbol_entry);
+    self.symbol_table.push(frame);
 
-    // load params into symbol table
-    for (i, expr) in exprs.iter().enumerate() {
-      let param_name = params.get(i).unwrap().to_string(); // TODO: check error
-      let expr_val = self.run_expr(expr)?;
-      if let Some(table_for_scope) = self.symbol_table.last_mut() {
-        table_for_scope.insert(
-          param_name.clone(),
+    // break and continue can't reach loops outside of the function
+    let loop_depth = self.loop_depth;
+    self.loop_depth = 0;
+    self.call_depth += 1;
+    let result = self.run_function_body(closure, bound);
+    self.call_depth -= 1;
+    self.nesting -= 1;
+    self.loop_depth = loop_depth;
+    self.symbol_table = caller_scopes;
+    self.source = caller_source;
+    let value = result.map_err(|mut e| {
+      e.stack.push(Frame {
+        function: name.to_string(),
+        call_site: self.location(call_expr.span()),
+      });
+      e
+    })?;
+    if let Some(ty) = &closure.ret {
+      let found = value.annotation_type();
+      if !ty.accepts(&found) {
+        let e = RuntimeError::new(
+          ErrorKind::Type,
+          format!("`{}` should return {}, found {}", name, ty, found),
+        );
+        return Err(self.call_error(e, call_expr, closure));
+      }
+    }
+    Ok(value)
+  }
+
+  /// Points an error about how a function was called at the call, noting
+  /// where the function was defined
+  fn call_error(
+    &self,
+    mut error: RuntimeError,
+    call_expr: &Meta<Expr>,
+    closure: &Closure,
+  ) -> RuntimeError {
+    error.location = Some(self.location(call_expr.span()));
+    error.notes.push((
+      Location {
+        source: closure.source.clone(),
+        span: closure.span,
+      },
+      "function defined here".to_string(),
+    ));
+    error
+  }
+
+  /// Binds the parameters of a function in its frame, which must be the
+  /// innermost scope, then runs its body
+  fn run_function_body(
+    &mut self,
+    closure: &Closure,
+    mut bound: Params,
+  ) -> Result<Object, RuntimeError> {
+    for param in &closure.params {
+      let val = match (bound.opt(&param.name), &param.default) {
+        (Some(val), _) => val,
+        (None, Some(default)) => self.run_expr(default)?,
+        (None, None) => bound.get(&param.name)?,
+      };
+      if let Some(table_for_scope) = self.symbol_table.last() {
+        table_for_scope.borrow_mut().insert(
+          param.name.clone(),
           SymbolEntry::Variable(VarEntry {
-            name: param_name.clone(),
-            value: SymbolVal::Object(expr_val),
+            name: param.name.clone(),
+            value: val,
           }),
         );
       }
     }
 
-    self.run_stmt(stmt)?;
+    match self.run_stmt(&closure.body)? {
+      Flow::Return(return_val) => Ok(return_val),
+      _ => Ok(Object::None),
+    }
+  }
 
-    let return_val = get_function(CURRENT_FUNCTION_CALL_KEY, &self.symbol_table)
-      .unwrap()
-      .clone()
-      .return_val
-      .unwrap_or(Object::Number(0.0));
-    self.symbol_table.pop();
-    Ok(return_val)
+  /// Processes an AST index `object[index]` into a list or string.
+  /// Negative indices count back from the end.
+  fn handle_index(
+    &mut self,
+    expr: &Meta<Expr>,
+    object: &Meta<Expr>,
+    index: &Meta<Expr>,
+  ) -> Result<Object, RuntimeError> {
+    let object = self.run_expr(object)?;
+    let len = self.sequence_len(expr, &object)?;
+    let i = self.run_index(index, len)?;
+    if i >= len {
+      return self.error(
+        ErrorKind::Value,
+        format!(
+          "Index out of range for {} of length {}",
+          object.type_name(),
+          len
+        ),
+        Some(index.span()),
+      );
+    }
+    Ok(match object {
+      Object::List(mut items) => items.swap_remove(i),
+      Object::Str(s) => Object::Str(s.chars().nth(i).unwrap().to_string()),
+      _ => unreachable!(),
+    })
+  }
+
+  /// Processes an AST slice `object[start:end]` of a list or string. Either
+  /// bound may be omitted, and negative bounds count back from the end.
+  fn handle_slice(
+    &mut self,
+    expr: &Meta<Expr>,
+    object: &Meta<Expr>,
+    start: &Option<Box<Meta<Expr>>>,
+    end: &Option<Box<Meta<Expr>>>,
+  ) -> Result<Object, RuntimeError> {
+    let object = self.run_expr(object)?;
+    let len = self.sequence_len(expr, &object)?;
+    let start = match start {
+      Some(start) => self.run_index(start, len)?,
+      None => 0,
+    };
+    let end = match end {
+      Some(end) => self.run_index(end, len)?,
+      None => len,
+    };
+    if start > end || end > len {
+      return self.error(
+        ErrorKind::Value,
+        format!(
+          "Slice {}:{} out of range for {} of length {}",
+          start,
+          end,
+          object.type_name(),
+          len
+        ),
+        Some(expr.span()),
+      );
+    }
+    Ok(match object {
+      Object::List(items) => Object::List(items[start..end].to_vec()),
+      Object::Str(s) => Object::Str(s.chars().skip(start).take(end - start).collect()),
+      _ => unreachable!(),
+    })
+  }
+
+  /// Gets the length of an object that can be indexed
+  fn sequence_len(&self, expr: &Meta<Expr>, object: &Object) -> Result<usize, RuntimeError> {
+    match object {
+      Object::List(items) => Ok(items.len()),
+      Object::Str(s) => Ok(s.chars().count()),
+      _ => self.error(
+        ErrorKind::Type,
+        format!("Cannot index into {}", object.type_name()),
+        Some(expr.span()),
+      ),
+    }
+  }
+
+  /// Evaluates an index into a sequence of the given length, resolving
+  /// negative indices relative to the end
+  fn run_index(&mut self, index: &Meta<Expr>, len: usize) -> Result<usize, RuntimeError> {
+    let i = match get_number(self.run_expr(index)?) {
+      Ok(i) if i.fract() == 0.0 => i,
+      Ok(i) => {
+        return self.error(
+          ErrorKind::Value,
+          format!("Index must be a whole number, got {}", i),
+          Some(index.span()),
+        )
+      }
+      Err(e) => return self.locate(e, index.span()),
+    };
+    let resolved = if i < 0.0 { i + len as f64 } else { i };
+    if resolved < 0.0 {
+      return self.error(
+        ErrorKind::Value,
+        format!("Index {} out of range for length {}", i, len),
+        Some(index.span()),
+      );
+    }
+    Ok(resolved as usize)
   }
 
   /// Processes an AST identifier
   fn handle_identifier(&mut self, expr: &Meta<Expr>, name: &str) -> Result<Object, RuntimeError> {
-    if let Some(var) = get_var(name, &self.symbol_table) {
-      if let SymbolVal::Object(ref obj) = var.value {
-        return Ok(obj.clone());
-      }
+    if let Some(obj) = get_var(name, &self.symbol_table) {
+      return Ok(obj);
     }
     self.error(
+      ErrorKind::Name,
       format!("Couldn't find identifier: {}", name),
-      Some(expr.byte_offset),
+      Some(expr.span()),
     )
   }
 
@@ -381,6 +1959,15 @@ impl Runtime {
     Ok(s)
   }
 
+  /// Processes an AST interpolated string, joining the text of each part
+  fn handle_interpolate(&mut self, parts: &Vec<Meta<Expr>>) -> Result<Object, RuntimeError> {
+    let mut s = String::new();
+    for part in parts {
+      s.push_str(&stdlib::get_str_rep(&self.run_expr(part)?));
+    }
+    Ok(Object::Str(s))
+  }
+
   /// Processes an AST list
   fn handle_list(&mut self, l: &Vec<Meta<Expr>>) -> Result<Object, RuntimeError> {
     let mut evaled_exprs = vec![];
@@ -391,11 +1978,100 @@ impl Runtime {
     Ok(Object::List(evaled_exprs))
   }
 
+  /// Processes an AST map literal
+  fn handle_map(&mut self, entries: &Vec<(String, Meta<Expr>)>) -> Result<Object, RuntimeError> {
+    let mut map = BTreeMap::new();
+    for (key, expr) in entries {
+      if map.contains_key(key) {
+        return self.error(
+          ErrorKind::Value,
+          format!("Duplicate key `{}` in map", key),
+          Some(expr.span()),
+        );
+      }
+      let val = self.run_expr(expr)?;
+      map.insert(key.clone(), val);
+    }
+    Ok(Object::Map(map))
+  }
+
+  /// Processes an AST range used as a value, producing the list of numbers
+  /// (or quantities) from start towards end in increments of step (1 by
+  /// default)
+  fn handle_range(
+    &mut self,
+    expr: &Meta<Expr>,
+    start: &Meta<Expr>,
+    end: &Meta<Expr>,
+    step: &Option<Box<Meta<Expr>>>,
+    inclusive: bool,
+  ) -> Result<Object, RuntimeError> {
+    let mut items = vec![];
+    for item in self.make_range(expr, start, end, step, inclusive)? {
+      if items.len() == RANGE_LIST_LIMIT {
+        return self.error(
+          ErrorKind::Limit,
+          format!(
+            "Range has more than {} elements, too many to make a list of",
+            RANGE_LIST_LIMIT
+          ),
+          Some(expr.span()),
+        );
+      }
+      self.step(expr.span())?;
+      items.push(item);
+    }
+    Ok(Object::List(items))
+  }
+
+  /// Runs an AST expr that should produce a number or quantity
+  fn run_quantity(&mut self, expr: &Meta<Expr>) -> Result<(f64, Dimension), RuntimeError> {
+    let value = self.run_expr(expr)?;
+    match get_quantity(value) {
+      Ok(quantity) => Ok(quantity),
+      Err(e) => self.locate(e, expr.span()),
+    }
+  }
+
+  /// Works out the bounds and step of an AST range, ready to iterate over
+  fn make_range(
+    &mut self,
+    expr: &Meta<Expr>,
+    start: &Meta<Expr>,
+    end: &Meta<Expr>,
+    step: &Option<Box<Meta<Expr>>>,
+    inclusive: bool,
+  ) -> Result<RangeIter, RuntimeError> {
+    let (start, start_dim) = self.run_quantity(start)?;
+    let (end, end_dim) = self.run_quantity(end)?;
+    let (step, step_dim) = match step {
+      Some(step) => self.run_quantity(step)?,
+      None => (1.0, Dimension::NONE),
+    };
+    let dim = self.common_dimension("make a range of", expr, start_dim, end_dim)?;
+    let dim = self.common_dimension("make a range of", expr, dim, step_dim)?;
+    if step == 0.0 || !step.is_finite() {
+      return self.error(
+        ErrorKind::Value,
+        format!("Range step must be a non-zero number, got: {}", step),
+        Some(expr.span()),
+      );
+    }
+    Ok(RangeIter {
+      start: start,
+      end: end,
+      step: step,
+      inclusive: inclusive,
+      dim: dim,
+      index: 0.0,
+    })
+  }
+
   /// Runs a stdlib functions and returns the result as an Object
   fn run_stdlib_function_call(
     &mut self,
     function_name: &str,
-    args: Vec<Object>,
+    args: Args,
   ) -> Result<Object, RuntimeError> {
     match function_name {
       "print" => stdlib::std_print(&mut self.stdout, args),
@@ -404,68 +2080,147 @@ impl Runtime {
       "move" => stdlib::std_move(args),
       "difference" => stdlib::std_difference(args),
       "rotate_x" => stdlib::std_rotate_x(args),
+      "translate" => stdlib::std_translate(args),
+      "Vector" => stdlib::std_make_vector(args),
+      "Point" => stdlib::std_make_point(args),
+      "dot" => stdlib::std_dot(args),
+      "cross" => stdlib::std_cross(args),
+      "normalize" => stdlib::std_normalize(args),
+      "length" => stdlib::std_length(args),
       "display" => stdlib::std_display(args),
-      "write_stl" => stdlib::std_write_stl(args),
+      "write_stl" => stdlib::std_write_stl(&mut self.outputs, args),
+      "len" => stdlib::std_len(args),
+      "format" => stdlib::std_format(args),
+      "str" => stdlib::std_str(args),
       _ => self.error(
+        ErrorKind::Name,
         format!("Couldn't find stdlib function with name: {}", function_name),
         None,
       ),
     }
   }
 
-  /// Inserts stdlib functions into the top level of the
+  /// Inserts stdlib functions and constants into the top level of the
   /// symbol table
   fn add_stdlib(&mut self) {
-    let toplevel = self.symbol_table.get_mut(0).unwrap();
-    for std_lib_function in STD_LIB_FUNCTIONS.iter() {
+    let mut toplevel = self.symbol_table[0].borrow_mut();
+    for (name, value) in builtins() {
       toplevel.insert(
-        std_lib_function.to_string(),
+        name.clone(),
         SymbolEntry::Variable(VarEntry {
-          name: std_lib_function.to_string(),
-          value: SymbolVal::StdLib(std_lib_function.to_string()),
+          name: name,
+          value: value,
         }),
       );
     }
   }
 
-  /// Generates a runtime error specifying the line number in the source code
-  fn error(&self, msg: String, byte_offset: Option<usize>) -> Result<Object, RuntimeError> {
-    let msg = if let Some(byte_offset) = byte_offset {
-      let line = get_line_number(&self.source_code, byte_offset);
-      format!("Runtime Error: {} at line {}", msg, line)
-    } else {
-      format!("Runtime Error: {}", msg)
-    };
-    Err(RuntimeError::new(msg))
+  /// Generates a runtime error pointing at a span of the current source
+  fn error<T>(&self, kind: ErrorKind, msg: String, span: Option<Span>) -> Result<T, RuntimeError> {
+    let mut error = RuntimeError::new(kind, msg);
+    error.location = span.map(|span| self.location(span));
+    Err(error)
+  }
+
+  /// Points an error raised without knowing where it happened, such as one
+  /// from a stdlib function, at a span of the current source
+  fn locate<T>(&self, mut error: RuntimeError, span: Span) -> Result<T, RuntimeError> {
+    if error.location.is_none() {
+      error.location = Some(self.location(span));
+    }
+    Err(error)
+  }
+
+  fn location(&self, span: Span) -> Location {
+    Location {
+      source: self.source.clone(),
+      span: span,
+    }
+  }
+}
+
+impl Drop for Runtime {
+  fn drop(&mut self) {
+    for scope in self.scopes.drain(..) {
+      if let Some(scope) = scope.upgrade() {
+        scope.borrow_mut().clear();
+      }
+    }
+  }
+}
+
+/// Stdlib functions and constants, which every program and module starts
+/// with
+fn builtins() -> Vec<(String, Object)> {
+  let mut builtins: Vec<(String, Object)> = STD_LIB_FUNCTIONS
+    .iter()
+    .map(|name| {
+      (
+        name.to_string(),
+        Object::Function(Function::StdLib(name.to_string())),
+      )
+    })
+    .collect();
+  builtins.push(("none".to_string(), Object::None));
+  builtins.push((
+    "origin".to_string(),
+    Object::Point(Point::new([0.0, 0.0, 0.0])),
+  ));
+  for axis in [Axis::X, Axis::Y, Axis::Z].iter() {
+    builtins.push((
+      format!("{:?}", axis),
+      Object::Vector(axis.components(1.0).into()),
+    ));
+  }
+  builtins
+}
+
+/// The name a module is bound to when it's imported without an alias: its
+/// file name without the extension, if that is a valid identifier
+fn module_name(path: &str) -> Option<String> {
+  let stem = Path::new(path).file_stem()?.to_str()?;
+  let mut chars = stem.chars();
+  let valid = chars
+    .next()
+    .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+  if valid {
+    Some(stem.to_string())
+  } else {
+    None
   }
 }
 
 /// Searches a symbol table for a variable matching the
 /// given name
-fn get_var<'a>(
-  var_name: &str,
-  symbol_table: &'a Vec<HashMap<String, SymbolEntry>>,
-) -> Option<&'a VarEntry> {
+fn get_var(var_name: &str, symbol_table: &[Scope]) -> Option<Object> {
   for table in symbol_table.iter().rev() {
-    if let Some(SymbolEntry::Variable(var)) = table.get(var_name) {
-      return Some(var);
+    if let Some(SymbolEntry::Variable(var)) = table.borrow().get(var_name) {
+      return Some(var.value.clone());
     }
   }
   None
 }
 
-/// Searches a symbol table for a function matching the
-/// given name
-fn get_function<'a>(
-  func_name: &str,
-  symbol_table: &'a Vec<HashMap<String, SymbolEntry>>,
-) -> Option<&'a FunctionMetadataEntry> {
+/// Finds the function a method call refers to. Variables that aren't
+/// functions are skipped, so that e.g. a local `length` doesn't hide the
+/// `length` function from `v.length()`; stdlib functions are found even if
+/// every variable of the same name is shadowed.
+fn get_method(name: &str, symbol_table: &[Scope]) -> Option<Function> {
   for table in symbol_table.iter().rev() {
-    if let Some(SymbolEntry::Function(func)) = table.get(func_name) {
-      return Some(func);
+    if let Some(SymbolEntry::Variable(VarEntry {
+      value: Object::Function(function),
+      ..
+    })) = table.borrow().get(name)
+    {
+      return Some(function.clone());
     }
   }
-  None
+  if STD_LIB_FUNCTIONS.contains(&name) {
+    Some(Function::StdLib(name.to_string()))
+  } else {
+    None
+  }
 }
 
 // Extracts a number from an Object
@@ -473,10 +2228,81 @@ pub fn get_number(object: Object) -> Result<f64, RuntimeError> {
   if let Object::Number(num) = object {
     Ok(num)
   } else {
-    Err(RuntimeError::new(format!(
-      "Object is not a number: {:?}",
-      object
-    )))
+    Err(RuntimeError::new(
+      ErrorKind::Type,
+      format!("Object is not a number: {:?}", object),
+    ))
+  }
+}
+
+/// Whether an Object is a vector or a point
+fn is_geometric(object: &Object) -> bool {
+  match object {
+    Object::Vector(_) | Object::Point(_) => true,
+    _ => false,
+  }
+}
+
+/// The factor a number or a length scales a vector by
+fn scale(object: &Object) -> Option<f64> {
+  match object {
+    Object::Number(n) => Some(*n),
+    Object::Quantity(n, dim) if *dim == Dimension::LENGTH => Some(*n),
+    _ => None,
+  }
+}
+
+/// Whether an Object is a number or a quantity
+fn is_numeric(object: &Object) -> bool {
+  match object {
+    Object::Number(_) | Object::Quantity(..) => true,
+    _ => false,
+  }
+}
+
+/// Builds a quantity Object, falling back to a plain number when there
+/// is no dimension
+pub fn make_quantity(value: f64, dim: Dimension) -> Object {
+  if dim.is_none() {
+    Object::Number(value)
+  } else {
+    Object::Quantity(value, dim)
+  }
+}
+
+/// Extracts a value and its dimension from a number or quantity Object
+pub fn get_quantity(object: Object) -> Result<(f64, Dimension), RuntimeError> {
+  match object {
+    Object::Number(num) => Ok((num, Dimension::NONE)),
+    Object::Quantity(num, dim) => Ok((num, dim)),
+    _ => Err(RuntimeError::new(
+      ErrorKind::Type,
+      format!("Object is not a number: {:?}", object),
+    )),
+  }
+}
+
+/// Extracts a length in millimeters from an Object. Plain numbers are
+/// taken to already be in millimeters.
+pub fn get_length(object: Object) -> Result<f64, RuntimeError> {
+  match get_quantity(object)? {
+    (num, dim) if dim.is_none() || dim == Dimension::LENGTH => Ok(num),
+    (_, dim) => Err(RuntimeError::new(
+      ErrorKind::Type,
+      format!("Expected a length, got a quantity in {}", dim),
+    )),
+  }
+}
+
+/// Extracts an angle in radians from an Object. Plain numbers are taken
+/// to already be in radians.
+pub fn get_angle(object: Object) -> Result<f64, RuntimeError> {
+  match get_quantity(object)? {
+    (num, dim) if dim.is_none() || dim == Dimension::ANGLE => Ok(num),
+    (_, dim) => Err(RuntimeError::new(
+      ErrorKind::Type,
+      format!("Expected an angle, got a quantity in {}", dim),
+    )),
   }
 }
 
@@ -485,10 +2311,22 @@ pub fn get_solid(object: Object) -> Result<Solid, RuntimeError> {
   if let Object::Solid(solid) = object {
     Ok(solid)
   } else {
-    Err(RuntimeError::new(format!(
-      "Object is not a solid: {:?}",
-      object
-    )))
+    Err(RuntimeError::new(
+      ErrorKind::Type,
+      format!("Object is not a solid: {:?}", object),
+    ))
+  }
+}
+
+/// Extracts a vector from an Object
+pub fn get_vector(object: Object) -> Result<Vector, RuntimeError> {
+  if let Object::Vector(vector) = object {
+    Ok(vector)
+  } else {
+    Err(RuntimeError::new(
+      ErrorKind::Type,
+      format!("Object is not a vector: {:?}", object),
+    ))
   }
 }
 
@@ -497,9 +2335,703 @@ pub fn get_str(object: Object) -> Result<String, RuntimeError> {
   if let Object::Str(solid) = object {
     Ok(solid)
   } else {
-    Err(RuntimeError::new(format!(
-      "Object is not a string: {:?}",
-      object
-    )))
+    Err(RuntimeError::new(
+      ErrorKind::Type,
+      format!("Object is not a string: {:?}", object),
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parser::parse::parse_program;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+  use std::thread;
+
+  /// A writer that can be handed to the runtime while the test keeps a
+  /// handle on what was written
+  #[derive(Clone, Default)]
+  struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  /// Runs a program, returning everything it printed
+  fn run(source: &str) -> Result<String, RuntimeError> {
+    let source = source.to_string();
+    let ast = parse_program(&source).expect("program should parse");
+    let buf = SharedBuf::default();
+    Runtime::new(source, Some(Box::new(buf.clone()))).run(&ast)?;
+    let out = buf.0.borrow().clone();
+    Ok(String::from_utf8(out).unwrap())
+  }
+
+  #[test]
+  fn test_comparison_and_logical_operators() {
+    assert_eq!(
+      run("print(1 < 2); print(2 <= 1); print(\"a\" == \"a\"); print(!(1 != 1));").unwrap(),
+      "true\nfalse\ntrue\ntrue\n"
+    );
+    assert_eq!(
+      run("print(true || undefined); print(false && undefined);").unwrap(),
+      "true\nfalse\n",
+      "logical operators short-circuit"
+    );
+    assert_eq!(
+      run("for (i = 0; i < 3; i = i + 1) print(i);").unwrap(),
+      "0\n1\n2\n"
+    );
+  }
+
+  #[test]
+  fn test_else_branches() {
+    let program = "
+      fn size(n) {
+        if (n < 10) return \"small\";
+        else if (n < 100) return \"medium\";
+        else return \"large\";
+      }
+      print(size(1)); print(size(50)); print(size(500));
+      if (true) if (false) print(1); else print(2);
+    ";
+    assert_eq!(
+      run(program).unwrap(),
+      "small\nmedium\nlarge\n2\n",
+      "else binds to the nearest if"
+    );
+  }
+
+  #[test]
+  fn test_while_break_continue() {
+    let program = "
+      i = 0;
+      while (true) {
+        i = i + 1;
+        if (i % 2 == 0) { continue; }
+        if (i > 7) { break; }
+        print(i);
+      }
+      for (j = 0; j < 10; j = j + 1) { if (j == 2) break; print(j); }
+    ";
+    assert_eq!(run(program).unwrap(), "1\n3\n5\n7\n0\n1\n");
+  }
+
+  #[test]
+  fn test_for_in() {
+    assert_eq!(
+      run("for i in 0..3 { print(i); } for i in 3..=1 step -1 { print(i); }").unwrap(),
+      "0\n1\n2\n3\n2\n1\n"
+    );
+    assert_eq!(
+      run("for x in [\"a\", 1] { print(x); } for i in 0..1 step 1 / 4 { if (i == 1 / 2) break; print(i); }")
+        .unwrap(),
+      "a\n1\n0\n0.25\n"
+    );
+    // only ranges used as values are made into lists
+    assert_eq!(
+      run("for i in 0..1e12 { if (i == 2) break; print(i); }").unwrap(),
+      "0\n1\n"
+    );
+    assert_eq!(run("x = 0..1e12;").unwrap_err().kind, ErrorKind::Limit);
+    assert!(
+      run("for i in [1] { } print(i);").is_err(),
+      "loop variable is scoped to the body"
+    );
+  }
+
+  #[test]
+  fn test_units() {
+    assert_eq!(
+      run("print(1cm + 5mm); print(180deg == 0.5turn); print(2in / 1mm); print(10mm * 2);")
+        .unwrap(),
+      "15mm\ntrue\n50.8\n20mm\n"
+    );
+    let cases = [
+      ("x = 1mm + 1rad;", "Cannot add quantities in mm and rad"),
+      (
+        "x = Box(1mm, 1mm, 90deg);",
+        "Expected a length, got a quantity in rad",
+      ),
+      (
+        "x = Box(1mm, 1mm, 1mm * 1mm);",
+        "Expected a length, got a quantity in mm^2",
+      ),
+    ];
+    for (source, msg) in cases.iter() {
+      let error = run(source).unwrap_err();
+      assert_eq!(error.kind, ErrorKind::Type, "{}", source);
+      assert_eq!(error.msg, *msg);
+    }
+
+    // the grammar never builds these, but the runtime still refuses them
+    let number = |n| Box::new(Meta::new(Expr::Number(n), 0, 1));
+    let exprs = [
+      (
+        Expr::Binary(Operator::Not, number(1.0), number(2.0)),
+        "Operator Not is not defined for numbers",
+      ),
+      (
+        Expr::Unary(Operator::Add, number(1.0)),
+        "Operator Add can't be applied to a single number",
+      ),
+    ];
+    for (expr, msg) in exprs.iter() {
+      let program = vec![Meta::new(Stmt::Expr(Meta::new(expr.clone(), 0, 1)), 0, 1)];
+      let error = Runtime::new("1".to_string(), None)
+        .run(&program)
+        .unwrap_err();
+      assert_eq!(error.kind, ErrorKind::Type);
+      assert_eq!(error.msg, *msg);
+      assert!(error.location.is_some());
+    }
+  }
+
+  #[test]
+  fn test_keyword_arguments() {
+    assert_eq!(
+      run("fn f(a, b) { return a - b; } print(f(1, b=2)); print(f(b=1, a=3));").unwrap(),
+      "-1\n2\n"
+    );
+    assert!(run("x = Box(length=1, width=2, height=3);").is_ok());
+    let cases = [
+      (
+        "x = Box(length=1, 2, 3);",
+        "Positional argument follows keyword argument",
+      ),
+      (
+        "x = Box(1, 2, 3, length=1);",
+        "Box got multiple values for parameter `length`",
+      ),
+      (
+        "x = Box(1, 2, 3, depth=1);",
+        "Box has no parameter named `depth`",
+      ),
+      (
+        "fn f(a) { } f(a=1, a=2);",
+        "f got multiple values for parameter `a`",
+      ),
+      ("fn f(a, b) { } f(1);", "f is missing argument `b`"),
+    ];
+    for (source, msg) in cases.iter() {
+      let error = run(source).unwrap_err();
+      assert_eq!(error.kind, ErrorKind::Argument, "{}", source);
+      assert_eq!(error.msg, *msg);
+    }
+  }
+
+  #[test]
+  fn test_default_parameters() {
+    let program = "
+      fn bracket(width, thickness = 3, holes = width / 10) {
+        return [width, thickness, holes];
+      }
+      print(bracket(20)); print(bracket(20, 1)); print(bracket(50, holes=2));
+    ";
+    assert_eq!(
+      run(program).unwrap(),
+      "[\"20\", \"3\", \"2\"]\n[\"20\", \"1\", \"2\"]\n[\"50\", \"3\", \"2\"]\n"
+    );
+    let error = run("fn f(a, b = 1) { } f(b=2);").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Argument);
+    assert_eq!(error.msg, "f is missing argument `a`");
+  }
+
+  #[test]
+  fn test_return_annotations() {
+    assert_eq!(
+      run("fn f(n) -> Number { if (n) { return 1; } } print(f(true));").unwrap(),
+      "1\n"
+    );
+    let e = run("fn f(n) -> Number { if (n) { return 1; } } f(false);").unwrap_err();
+    assert_eq!(e.kind, ErrorKind::Type);
+    assert_eq!(e.msg, "`f` should return Number, found None");
+    let e = run("g = fn() -> Str { return 1; }; g();").unwrap_err();
+    assert_eq!(e.msg, "`<anonymous>` should return Str, found Number");
+  }
+
+  #[test]
+  fn test_members_and_methods() {
+    let program = "
+      fn double(x, factor = 2) { return x * factor; }
+      print(3.double()); print(3.double(factor=3));
+      s = Box(1, 1, 1).rotate_x(90deg).translate(1, 2, 3);
+      print(s.faces.length);
+      print(s.faces.length.double());
+      p = Plane(1, 2, 3);
+      print(p.point.y); print(p.norm.length == 1);
+    ";
+    assert_eq!(run(program).unwrap(), "6\n9\n6\n12\n2mm\ntrue\n");
+    assert!(run("x = 1; y = x.faces;").is_err());
+    // variables that aren't functions don't hide methods
+    let program = "
+      length = 5;
+      print(Vector(3, 4, 0).length());
+      fn double(x) { return x * 2; }
+      fn g() { double = 1; return 2.double(); }
+      print(g());
+    ";
+    assert_eq!(run(program).unwrap(), "5mm\n4\n");
+  }
+
+  #[test]
+  fn test_indexing_and_destructuring() {
+    let program = "
+      l = [1, 2, 3, 4];
+      print(l[0]); print(l[-1]); print(l[1:3]); print(l[:-2]); print(\"hello\"[1:]);
+      fn pair() { return [1, [2, 3]]; }
+      [a, b] = pair();
+      print(a); print(b[1]);
+    ";
+    assert_eq!(
+      run(program).unwrap(),
+      "1\n4\n[\"2\", \"3\"]\n[\"1\", \"2\"]\nello\n1\n3\n"
+    );
+    assert!(run("l = [1]; x = l[1];").is_err());
+    assert!(run("l = [1]; x = l[-2];").is_err());
+    assert!(run("l = [1]; x = l[1:0];").is_err());
+    assert!(run("[a, b] = [1];").is_err());
+    assert!(parse_program("[a, 1] = [1, 2];").is_err());
+  }
+
+  #[test]
+  fn test_maps() {
+    let program = "
+      fn bolt(length = 10mm) { return {shaft: length, head: {width: 5mm}}; }
+      b = bolt();
+      print(b.shaft); print(b.head.width); print({b: [1, 2], a: \"x\",});
+      { print(1); }
+    ";
+    assert_eq!(
+      run(program).unwrap(),
+      "10mm\n5mm\n{a: x, b: [\"1\", \"2\"]}\n1\n"
+    );
+    assert!(run("m = {a: 1}; x = m.b;").is_err());
+    assert!(run("m = {a: 1, a: 2};").is_err());
+  }
+
+  #[test]
+  fn test_csg_operators() {
+    let program = "
+      a = Box(2, 2, 2);
+      b = Box(1, 1, 1);
+      print(a - b == difference(a, b));
+      c = a; c -= b; print(c == a - b);
+      c = a; c |= b; print(c == (a | b)); print(a + b == (a | b));
+      c = a; c &= b; print(c == (a & b));
+      x = 1; x += 2; print(x);
+      for (i = 0; i < 3; i += 1) print(i);
+    ";
+    assert_eq!(
+      run(program).unwrap(),
+      "true\ntrue\ntrue\ntrue\ntrue\n3\n0\n1\n2\n"
+    );
+    assert!(run("x = 1 | 2;").is_err());
+    assert!(run("x = Box(1, 1, 1) * Box(1, 1, 1);").is_err());
+  }
+
+  #[test]
+  fn test_vector_arithmetic() {
+    let program = "
+      a = Vector(1, 0, 0);
+      b = Vector(0, 2, 0);
+      p = Point(1, 1, 1);
+      print((a + b).y); print((2 * a - b / 2).x); print((-a).x);
+      print((p + a).x); print((p - Point(0, 0, 0)) == Vector(1, 1, 1));
+      print(dot(a, b)); print(cross(a, b).z); print(normalize(b) == Vector(0, 1, 0));
+      print(b.length()); print(length(b)); print(b / length(b) == normalize(b));
+    ";
+    assert_eq!(
+      run(program).unwrap(),
+      "2mm\n2mm\n-1mm\n2mm\ntrue\n0\n2mm\ntrue\n2mm\n2mm\ntrue\n"
+    );
+    // lengths scale vectors too, so a length can set a direction's size
+    let program = "
+      h = 3mm;
+      print(h * Z == Vector(0, 0, 3)); print(Vector(1, 0, 0) * 2mm == 2X);
+      print((2X / 2mm).x); print((origin + h * Z).z == (h * Z).z);
+    ";
+    assert_eq!(run(program).unwrap(), "true\ntrue\n1mm\ntrue\n");
+    assert_eq!(
+      run("print(-2X); print(origin + 1.5Y); print([Z]);").unwrap(),
+      "Vector(-2mm, 0mm, 0mm)\nPoint(0mm, 1.5mm, 0mm)\n[\"Vector(0mm, 0mm, 1mm)\"]\n"
+    );
+    assert!(run("x = Point(0, 0, 0) + Point(0, 0, 0);").is_err());
+    assert!(run("x = Vector(1, 0, 0) * 1deg;").is_err());
+    assert!(run("x = normalize(Vector(0, 0, 0));").is_err());
+  }
+
+  #[test]
+  fn test_vector_literals() {
+    let program = "
+      p = origin + 2X - 3Z;
+      print(p.x); print(p.z); print(10Y == 10 * Y); print(1.5X + Y == Vector(1.5, 1, 0));
+    ";
+    assert_eq!(run(program).unwrap(), "2mm\n-3mm\ntrue\ntrue\n");
+  }
+
+  #[test]
+  fn test_first_class_functions() {
+    let program = "
+      fn apply(f, x) { return f(x); }
+      fn adder(n) { return fn (x) { return x + n; }; }
+      add2 = adder(2);
+      print(apply(add2, 1)); print(apply(fn (x) { return x * 10; }, 3));
+      show = print; show(\"shown\");
+      fn fact(n) { if (n <= 1) { return 1; } else { return n * fact(n - 1); } }
+      print(fact(5)); print(add2 == add2); print(add2 == adder(2));
+    ";
+    assert_eq!(run(program).unwrap(), "3\n30\nshown\n120\ntrue\nfalse\n");
+  }
+
+  #[test]
+  fn test_lexical_scoping() {
+    let program = "
+      fn read_secret() { return secret; }
+      fn caller() { secret = 1; return read_secret(); }
+      print(caller());
+    ";
+    assert!(run(program).is_err(), "callee saw the caller's locals");
+    let program = "
+      scale = 2;
+      fn scaled(x) { return x * scale; }
+      scale = 3;
+      print(scaled(1));
+    ";
+    assert_eq!(run(program).unwrap(), "3\n");
+  }
+
+  #[test]
+  fn test_early_return() {
+    let program = "
+      fn find(items, target) {
+        for item in items {
+          while (true) { if (item == target) { return \"found\"; } break; }
+        }
+        print(\"not found\");
+        return;
+      }
+      print(find([1, 2, 3], 2)); print(find([1], 5));
+      fn nothing() { }
+      print(nothing() == none);
+      fn first() { return 1; print(\"unreachable\"); return 2; }
+      print(first());
+      print(print(0));
+    ";
+    assert_eq!(
+      run(program).unwrap(),
+      "found\nnot found\nnone\ntrue\n1\n0\nnone\n"
+    );
+    assert!(run("return 1;").is_err());
+  }
+
+  #[test]
+  fn test_imports() {
+    let dir = std::env::temp_dir().join(format!("ourcad_imports_{}", std::process::id()));
+    let lib = dir.join("lib");
+    fs::create_dir_all(&lib).unwrap();
+    let write = |path: &Path, code: &str| fs::write(path, code).unwrap();
+    write(
+      &lib.join("fasteners.oc"),
+      "print(\"loading fasteners\"); fn bolt(length = 5mm) { return length * 2; }",
+    );
+    write(
+      &dir.join("helper.oc"),
+      "import \"fasteners.oc\" as f; fn double(x) { return f.bolt(x); }",
+    );
+    write(&dir.join("a.oc"), "import \"b.oc\";");
+    write(&dir.join("b.oc"), "import \"a.oc\";");
+
+    let run_file = |source: &str| -> Result<String, RuntimeError> {
+      let main = dir.join("main.oc");
+      write(&main, source);
+      let ast = parse_program(source).expect("program should parse");
+      let buf = SharedBuf::default();
+      let mut runtime = Runtime::new(source.to_string(), Some(Box::new(buf.clone())));
+      runtime.set_file(main);
+      runtime.add_library_path(lib.clone());
+      runtime.run(&ast)?;
+      let out = buf.0.borrow().clone();
+      Ok(String::from_utf8(out).unwrap())
+    };
+    assert_eq!(
+      run_file("import \"helper.oc\"; import \"fasteners.oc\"; print(helper.double(2mm)); print(fasteners.bolt());")
+        .unwrap(),
+      "loading fasteners\n4mm\n10mm\n"
+    );
+    let cycle = run_file("import \"a.oc\";").unwrap_err();
+    assert!(cycle.to_string().contains("Import cycle"), "{}", cycle);
+    assert!(run_file("import \"missing.oc\";").is_err());
+    write(&dir.join("typed.oc"), "fn f(x: Number) { return x; }");
+    write(&dir.join("untyped.oc"), "x = Box(1, 2, \"3\");");
+    assert_eq!(
+      run_file("import \"typed.oc\"; print(typed.f(1));").unwrap(),
+      "1\n"
+    );
+    let error = run_file("import \"typed.oc\"; typed.f(\"x\");").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Type);
+    let error = run_file("import \"untyped.oc\";").unwrap_err();
+    assert!(error.to_string().contains("has type errors"), "{}", error);
+    assert_eq!(
+      error.notes[0].1,
+      "Argument `height` of `Box` should be Number, found Str"
+    );
+    assert!(
+      error.render().contains("untyped.oc:1:15"),
+      "{}",
+      error.render()
+    );
+    write(&dir.join("broken.oc"), "x = ;");
+    let error = run_file("import \"broken.oc\";").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Syntax);
+    assert_eq!(error.notes[0].1, "Unexpected `;`");
+    assert!(
+      error.render().contains("broken.oc:1:5"),
+      "{}",
+      error.render()
+    );
+    for path in &["/etc/hostname", "../main.oc", "lib/../a.oc"] {
+      let error = run_file(&format!("import \"{}\" as m;", path)).unwrap_err();
+      assert!(error.to_string().contains("must be relative"), "{}", error);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_strings() {
+    let program = r#"
+      for i in 1..=2 { print("out/part_${i}.stl"); }
+      print("say \"hi\"\tand ${"nested ${1 + 1}"} {braces}");
+      print("a" + "b"); print(len("héllo")); print(len([1, 2, 3]));
+      print(format("{} x {} {{}}", 2mm, "b")); print(str(3.14159, 2)); print(str(1/2 * 1mm, 1));
+    "#;
+    assert_eq!(
+      run(program).unwrap(),
+      "out/part_1.stl\nout/part_2.stl\nsay \"hi\"\tand nested 2 {braces}\nab\n5\n3\n2mm x b {}\n3.14\n0.5mm\n"
+    );
+    assert!(run(r#"x = "a" + 1;"#).is_err());
+    assert!(run(r#"x = format("{}");"#).is_err());
+    for precision in &["1e30", "-1", "0.5", "18"] {
+      let source = format!("x = str(1, {});", precision);
+      let error = run(&source).unwrap_err();
+      assert_eq!(error.kind, ErrorKind::Value);
+      assert!(error.msg.contains("from 0 to 17"), "{}", error.msg);
+      assert_eq!(error.render().lines().nth(1), Some(" --> <stdin>:1:5"));
+    }
+  }
+
+  #[test]
+  fn test_loop_control_outside_loop() {
+    assert!(run("break;").is_err());
+    assert!(run("fn f() { continue; } while (true) { f(); }").is_err());
+  }
+
+  #[test]
+  fn test_non_boolean_condition() {
+    assert!(run("if (1) print(1);").is_err());
+    assert!(run("x = 1 && true;").is_err());
+  }
+
+  #[test]
+  fn test_rendered_errors() {
+    let error = run("fn f(a, b) {\n  return a;\n}\nx = f(1);").unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "Runtime Error: f is missing argument `b` at line 4"
+    );
+    let expected = [
+      "argument error: f is missing argument `b`",
+      " --> <stdin>:4:5",
+      "  |",
+      "4 | x = f(1);",
+      "  |     ^^^^",
+      "1 | fn f(a, b) {",
+      "  | ------------ function defined here",
+    ];
+    assert_eq!(error.render(), expected.join("\n"));
+
+    // operands that aren't numbers are pointed at
+    let error = run("x = [1] + 2;").unwrap_err();
+    let expected = [
+      "type error: Object is not a number: List([Number(1.0)])",
+      " --> <stdin>:1:5",
+      "  |",
+      "1 | x = [1] + 2;",
+      "  |     ^^^",
+    ];
+    assert_eq!(error.render(), expected.join("\n"));
+    let error = run("x = 0..\"a\";").unwrap_err();
+    assert_eq!(error.render().lines().nth(1), Some(" --> <stdin>:1:8"));
+  }
+
+  #[test]
+  fn test_call_stack() {
+    let program = "fn inner(x) {\n  return Box(x, 1, 1);\n}\nfn outer() {\n  return inner(true);\n}\nb = outer();";
+    let error = run(program).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Type);
+    let expected = [
+      "type error: Object is not a number: Bool(true)",
+      " --> <stdin>:2:10",
+      "  |",
+      "2 |   return Box(x, 1, 1);",
+      "  |          ^^^^^^^^^^^^",
+      "call stack (innermost first):",
+      "  0: inner called at <stdin>:5:10",
+      "  1: outer called at <stdin>:7:5",
+    ];
+    assert_eq!(error.render(), expected.join("\n"));
+  }
+
+  #[test]
+  fn test_limits() {
+    // runs on a thread with the stack the limits need, giving back the kind
+    // of error and how it renders
+    let run_limited = |source: &str, limits: Limits| -> Result<(), (ErrorKind, String)> {
+      let source = source.to_string();
+      thread::Builder::new()
+        .stack_size(limits.stack_size().unwrap())
+        .spawn(move || {
+          let ast = parse_program(&source).expect("program should parse");
+          let mut runtime = Runtime::new(source, Some(Box::new(SharedBuf::default())));
+          runtime.set_limits(limits);
+          runtime
+            .run(&ast)
+            .map(|_| ())
+            .map_err(|e| (e.kind, e.render()))
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+    };
+    let unbounded = Limits {
+      max_nesting: Some(usize::MAX),
+      ..Limits::default()
+    };
+    assert_eq!(unbounded.stack_size(), None);
+
+    let recursion = "fn f(n) { return f(n + 1); } f(0);";
+    let (kind, rendered) = run_limited(recursion, Limits::default()).unwrap_err();
+    assert_eq!(kind, ErrorKind::Limit);
+    assert!(rendered.contains("Maximum call depth of 100 exceeded"));
+    assert!(rendered.contains("... 80 more calls"));
+
+    // blocks nested inside each call use up the stack long before the call
+    // depth limit is reached
+    let nested = format!(
+      "fn f(n) {{ {} return f(n + 1); {} }} f(0);",
+      "if (true) { ".repeat(60),
+      "} ".repeat(60)
+    );
+    let (kind, rendered) = run_limited(&nested, Limits::default()).unwrap_err();
+    assert_eq!(kind, ErrorKind::Limit);
+    assert!(rendered.contains("Maximum nesting depth of 1000 exceeded"));
+
+    let forever = "while (true) { x = 1; }";
+    let steps = Limits {
+      max_steps: Some(1000),
+      ..Limits::default()
+    };
+    assert_eq!(
+      run_limited(forever, steps.clone()).unwrap_err().0,
+      ErrorKind::Limit
+    );
+    let time = Limits {
+      max_time: Some(Duration::from_millis(50)),
+      ..Limits::default()
+    };
+    assert_eq!(
+      run_limited(forever, time.clone()).unwrap_err().0,
+      ErrorKind::Limit
+    );
+    assert!(run_limited("x = 1; y = 2;", steps.clone()).is_ok());
+
+    // a single statement can do a lot of work
+    let huge = "x = 0..1e10 step 1;";
+    let (kind, rendered) = run_limited(huge, steps).unwrap_err();
+    assert_eq!(kind, ErrorKind::Limit);
+    assert!(rendered.contains("Limit of 1000 steps exceeded"));
+    assert_eq!(run_limited(huge, time).unwrap_err().0, ErrorKind::Limit);
+  }
+
+  #[test]
+  fn test_output_policy() {
+    let root = std::env::temp_dir().join(format!("ourcad_output_{}", std::process::id()));
+    let export = |source: &str, dry_run: bool| -> Result<Vec<Artifact>, RuntimeError> {
+      let source = source.to_string();
+      let ast = parse_program(&source).expect("program should parse");
+      let mut runtime = Runtime::new(source, Some(Box::new(SharedBuf::default())));
+      runtime.set_output_policy(OutputPolicy {
+        root: root.clone(),
+        dry_run: dry_run,
+      });
+      runtime.run(&ast)
+    };
+    let program = r#"write_stl(Box(1, 1, 1), "parts/box.stl");"#;
+    let manifest = export(program, true).unwrap();
+    assert_eq!(
+      manifest,
+      vec![Artifact {
+        path: root.join("parts/box.stl"),
+        format: "stl",
+        triangles: 12,
+      }]
+    );
+    assert!(!root.exists(), "dry run wrote files");
+
+    assert_eq!(export(program, false).unwrap(), manifest);
+    assert!(fs::read_to_string(root.join("parts/box.stl"))
+      .unwrap()
+      .starts_with("solid"));
+    for path in &["../box.stl", "/tmp/box.stl", "parts/"] {
+      let program = format!(r#"write_stl(Box(1, 1, 1), "{}");"#, path);
+      assert_eq!(export(&program, false).unwrap_err().kind, ErrorKind::Output);
+    }
+
+    // symlinks can't lead out of the output directory either
+    #[cfg(unix)]
+    {
+      let elsewhere = root.with_extension("elsewhere");
+      fs::create_dir_all(&elsewhere).unwrap();
+      std::os::unix::fs::symlink(&elsewhere, root.join("link")).unwrap();
+      std::os::unix::fs::symlink(elsewhere.join("f.stl"), root.join("f.stl")).unwrap();
+      for path in &["link/box.stl", "link/new/box.stl", "f.stl"] {
+        let program = format!(r#"write_stl(Box(1, 1, 1), "{}");"#, path);
+        assert_eq!(export(&program, false).unwrap_err().kind, ErrorKind::Output);
+      }
+      assert_eq!(fs::read_dir(&elsewhere).unwrap().count(), 0);
+      fs::remove_dir_all(&elsewhere).unwrap();
+    }
+
+    // files written before a failure are still reported
+    let source = r#"write_stl(Box(1, 1, 1), "a.stl"); x = y;"#.to_string();
+    let ast = parse_program(&source).expect("program should parse");
+    let mut runtime = Runtime::new(source, Some(Box::new(SharedBuf::default())));
+    runtime.set_output_policy(OutputPolicy {
+      root: root.clone(),
+      dry_run: true,
+    });
+    assert!(runtime.run(&ast).is_err());
+    assert_eq!(runtime.artifacts().len(), 1);
+    fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn test_scopes_freed() {
+    let source =
+      "fn f() { } s = Box(1, 1, 1); fn g() { fn h() { } return h; } k = g();".to_string();
+    let ast = parse_program(&source).expect("program should parse");
+    let mut runtime = Runtime::new(source, Some(Box::new(SharedBuf::default())));
+    runtime.run(&ast).unwrap();
+    let scopes: Vec<Weak<_>> = runtime.scopes.clone();
+    assert!(scopes.iter().any(|scope| scope.upgrade().is_some()));
+    drop(runtime);
+    assert!(scopes.iter().all(|scope| scope.upgrade().is_none()));
   }
 }
@@ -54,6 +54,16 @@ impl Transform {
       ],
     }
   }
+  pub fn translate(offset: Vector) -> Transform {
+    Transform {
+      cols: [
+        [1.0, 0.0, 0.0].into(),
+        [0.0, 1.0, 0.0].into(),
+        [0.0, 0.0, 1.0].into(),
+        offset,
+      ],
+    }
+  }
 }
 
 impl Mul<Vector> for Transform {
@@ -2,40 +2,103 @@ use boolean::{boolean, Boolean};
 #[cfg(feature = "display")]
 use display::display;
 use format::write_stl;
-use runtime::{get_number, get_solid, get_str, Object, RuntimeError};
-use solid::{Plane, Point, Solid, Transform, Vector};
-use std::fs::File;
+use output::Outputs;
+use runtime::{
+  get_angle, get_length, get_number, get_solid, get_str, get_vector, make_quantity, Args,
+  ErrorKind, Object, RuntimeError,
+};
+use solid::{small, Plane, Point, Solid, Transform, Unit, Vector};
 use std::io::Write;
+use units::Dimension;
 
-fn get_str_rep(obj: &Object) -> String {
+pub fn get_str_rep(obj: &Object) -> String {
   match obj {
     Object::Number(n) => format!("{}", n.to_string()),
+    Object::Quantity(n, dim) => format!("{}{}", n, dim),
+    Object::Bool(b) => format!("{}", b),
+    Object::None => "none".to_string(),
     Object::Str(s) => format!("{}", s),
     Object::List(l) => format!("{:?}", l.iter().map(get_str_rep).collect::<Vec<String>>()),
+    Object::Map(m) => format!(
+      "{{{}}}",
+      m.iter()
+        .map(|(k, v)| format!("{}: {}", k, get_str_rep(v)))
+        .collect::<Vec<String>>()
+        .join(", ")
+    ),
+    Object::Vector(v) => format!("Vector({})", components(&v.c)),
+    Object::Point(p) => format!("Point({})", components(&p.pos.c)),
     _ => format!("{:?}", obj),
   }
 }
 
-pub fn std_print(writer: &mut Box<Write>, args: Vec<Object>) -> Result<Object, RuntimeError> {
-  if let Some(arg) = args.get(0) {
-    write!(writer, "{}\n", get_str_rep(arg));
-  } else {
-    println!("Error: no arg");
-  }
-  Ok(Object::Number(0.0))
+/// Shows coordinates as lengths, e.g. `1mm, 0mm, -2.5mm`
+fn components(c: &[f64; 3]) -> String {
+  c.iter()
+    // adding zero turns -0 into 0
+    .map(|n| get_str_rep(&length(n + 0.0)))
+    .collect::<Vec<String>>()
+    .join(", ")
+}
+
+/// Converts a length in millimeters to an Object
+fn length(value: f64) -> Object {
+  make_quantity(value, Dimension::LENGTH)
+}
+
+/// Looks up a property of an object, e.g. `solid.faces` or `point.x`
+pub fn get_property(obj: &Object, name: &str) -> Result<Object, RuntimeError> {
+  let property = match (obj, name) {
+    (Object::Point(p), "x") => Some(length(p.pos.c[0])),
+    (Object::Point(p), "y") => Some(length(p.pos.c[1])),
+    (Object::Point(p), "z") => Some(length(p.pos.c[2])),
+    (Object::Vector(v), "x") => Some(length(v.c[0])),
+    (Object::Vector(v), "y") => Some(length(v.c[1])),
+    (Object::Vector(v), "z") => Some(length(v.c[2])),
+    (Object::Vector(v), "length") => Some(length(v.len())),
+    (Object::Edge(e), "a") => Some(Object::Point(e.a)),
+    (Object::Edge(e), "b") => Some(Object::Point(e.b)),
+    (Object::Edge(e), "length") => Some(length((e.b - e.a).len())),
+    (Object::Plane(p), "point") => Some(Object::Point(p.point)),
+    (Object::Plane(p), "norm") => Some(Object::Vector(p.norm.0)),
+    (Object::Face(f), "plane") => Some(Object::Plane(f.plane)),
+    (Object::Face(f), "edges") => Some(Object::List(f.edges().map(Object::Edge).collect())),
+    (Object::Solid(s), "faces") => Some(Object::List(
+      s.faces.iter().cloned().map(Object::Face).collect(),
+    )),
+    (Object::Map(m), _) => m.get(name).cloned(),
+    (Object::List(l), "length") => Some(Object::Number(l.len() as f64)),
+    (Object::Str(s), "length") => Some(Object::Number(s.chars().count() as f64)),
+    _ => None,
+  };
+  property.ok_or_else(|| {
+    RuntimeError::new(
+      ErrorKind::Name,
+      format!("{} has no property `{}`", obj.type_name(), name),
+    )
+  })
+}
+
+pub fn std_print(writer: &mut Box<Write>, args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("print", &["value"])?;
+  let arg = args.get("value")?;
+  write!(writer, "{}\n", get_str_rep(&arg));
+  Ok(Object::None)
 }
 
-pub fn std_make_box(args: Vec<Object>) -> Result<Object, RuntimeError> {
-  let l = get_number(args.get(0).unwrap().clone())?;
-  let w = get_number(args.get(1).unwrap().clone())?;
-  let h = get_number(args.get(2).unwrap().clone())?;
+pub fn std_make_box(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("Box", &["length", "width", "height"])?;
+  let l = get_length(args.get("length")?)?;
+  let w = get_length(args.get("width")?)?;
+  let h = get_length(args.get("height")?)?;
   Ok(Object::Solid(Solid::make_box([l, w, h])))
 }
 
-pub fn std_make_plane(args: Vec<Object>) -> Result<Object, RuntimeError> {
-  let p1 = get_number(args.get(0).unwrap().clone())?;
-  let p2 = get_number(args.get(1).unwrap().clone())?;
-  let p3 = get_number(args.get(2).unwrap().clone())?;
+pub fn std_make_plane(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("Plane", &["x", "y", "z"])?;
+  let p1 = get_length(args.get("x")?)?;
+  let p2 = get_length(args.get("y")?)?;
+  let p3 = get_length(args.get("z")?)?;
   let plane = Plane {
     point: Point {
       pos: [p1, p2, p3].into(),
@@ -45,34 +108,201 @@ pub fn std_make_plane(args: Vec<Object>) -> Result<Object, RuntimeError> {
   Ok(Object::Plane(plane))
 }
 
-pub fn std_move(args: Vec<Object>) -> Result<Object, RuntimeError> {
-  Ok(Object::Number(0.0))
+pub fn std_move(args: Args) -> Result<Object, RuntimeError> {
+  Ok(Object::None)
 }
 
-pub fn std_difference(args: Vec<Object>) -> Result<Object, RuntimeError> {
-  let box1 = get_solid(args.get(0).unwrap().clone())?;
-  let box2 = get_solid(args.get(1).unwrap().clone())?;
+pub fn std_difference(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("difference", &["target", "tool"])?;
+  let box1 = get_solid(args.get("target")?)?;
+  let box2 = get_solid(args.get("tool")?)?;
   let diff = boolean(&box1, &box2, Boolean::Difference);
   Ok(Object::Solid(diff))
 }
 
-pub fn std_rotate_x(args: Vec<Object>) -> Result<Object, RuntimeError> {
-  let box1 = get_solid(args.get(0).unwrap().clone())?;
-  let angle = get_number(args.get(1).unwrap().clone())?;
+pub fn std_rotate_x(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("rotate_x", &["solid", "angle"])?;
+  let box1 = get_solid(args.get("solid")?)?;
+  let angle = get_angle(args.get("angle")?)?;
   let rotated = Transform::rotate_x(angle) * box1;
   Ok(Object::Solid(rotated))
 }
 
-pub fn std_display(args: Vec<Object>) -> Result<Object, RuntimeError> {
-  let solid = get_solid(args.get(0).unwrap().clone())?;
+pub fn std_translate(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("translate", &["solid", "x", "y", "z"])?;
+  let solid = get_solid(args.get("solid")?)?;
+  let x = get_length(args.get("x")?)?;
+  let y = get_length(args.get("y")?)?;
+  let z = get_length(args.get("z")?)?;
+  let moved = Transform::translate([x, y, z].into()) * solid;
+  Ok(Object::Solid(moved))
+}
+
+pub fn std_make_vector(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("Vector", &["x", "y", "z"])?;
+  let x = get_length(args.get("x")?)?;
+  let y = get_length(args.get("y")?)?;
+  let z = get_length(args.get("z")?)?;
+  Ok(Object::Vector([x, y, z].into()))
+}
+
+pub fn std_make_point(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("Point", &["x", "y", "z"])?;
+  let x = get_length(args.get("x")?)?;
+  let y = get_length(args.get("y")?)?;
+  let z = get_length(args.get("z")?)?;
+  Ok(Object::Point(Point::new([x, y, z])))
+}
+
+pub fn std_dot(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("dot", &["a", "b"])?;
+  let a = get_vector(args.get("a")?)?;
+  let b = get_vector(args.get("b")?)?;
+  Ok(Object::Number(a * b))
+}
+
+pub fn std_cross(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("cross", &["a", "b"])?;
+  let a = get_vector(args.get("a")?)?;
+  let b = get_vector(args.get("b")?)?;
+  Ok(Object::Vector(a.cross(&b)))
+}
+
+pub fn std_normalize(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("normalize", &["vector"])?;
+  let v = get_vector(args.get("vector")?)?;
+  if v.len() < small {
+    return Err(RuntimeError::new(
+      ErrorKind::Value,
+      "Cannot normalize a zero-length vector".to_string(),
+    ));
+  }
+  Ok(Object::Vector(Unit::from(v).0))
+}
+
+pub fn std_length(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("length", &["vector"])?;
+  let v = get_vector(args.get("vector")?)?;
+  Ok(length(v.len()))
+}
+
+pub fn std_len(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("len", &["value"])?;
+  let len = match args.get("value")? {
+    Object::Str(s) => s.chars().count(),
+    Object::List(l) => l.len(),
+    Object::Map(m) => m.len(),
+    other => {
+      return Err(RuntimeError::new(
+        ErrorKind::Type,
+        format!("{} has no length", other.type_name()),
+      ))
+    }
+  };
+  Ok(Object::Number(len as f64))
+}
+
+/// Replaces each `{}` in a template with the text of the next argument.
+/// `{{` and `}}` stand for literal braces.
+pub fn std_format(args: Args) -> Result<Object, RuntimeError> {
+  if !args.named.is_empty() {
+    return Err(RuntimeError::new(
+      ErrorKind::Argument,
+      "format doesn't take keyword arguments".to_string(),
+    ));
+  }
+  let mut values = args.positional.into_iter();
+  let template = match values.next() {
+    Some(template) => get_str(template)?,
+    None => {
+      return Err(RuntimeError::new(
+        ErrorKind::Argument,
+        "Missing argument `template` for format".to_string(),
+      ))
+    }
+  };
+  let mut result = String::new();
+  let mut chars = template.chars().peekable();
+  while let Some(c) = chars.next() {
+    match (c, chars.peek()) {
+      ('{', Some('{')) | ('}', Some('}')) => {
+        chars.next();
+        result.push(c);
+      }
+      ('{', Some('}')) => {
+        chars.next();
+        match values.next() {
+          Some(value) => result.push_str(&get_str_rep(&value)),
+          None => {
+            return Err(RuntimeError::new(
+              ErrorKind::Argument,
+              "Not enough arguments for format".to_string(),
+            ))
+          }
+        }
+      }
+      _ => result.push(c),
+    }
+  }
+  if values.next().is_some() {
+    return Err(RuntimeError::new(
+      ErrorKind::Argument,
+      "Too many arguments for format".to_string(),
+    ));
+  }
+  Ok(Object::Str(result))
+}
+
+/// Most digits `str` shows after the decimal point, which is as many as an
+/// f64 has
+const MAX_PRECISION: usize = 17;
+
+/// Converts a value to a string. Numbers can be given a number of digits to
+/// show after the decimal point.
+pub fn std_str(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("str", &["value", "precision"])?;
+  let value = args.get("value")?;
+  let precision = match args.opt("precision") {
+    Some(precision) => get_number(precision)?,
+    None => return Ok(Object::Str(get_str_rep(&value))),
+  };
+  if !(precision >= 0.0 && precision <= MAX_PRECISION as f64) || precision.fract() != 0.0 {
+    return Err(RuntimeError::new(
+      ErrorKind::Value,
+      format!(
+        "Precision must be a whole number of digits from 0 to {}, got {}",
+        MAX_PRECISION, precision
+      ),
+    ));
+  }
+  let precision = precision as usize;
+  match value {
+    Object::Number(n) => Ok(Object::Str(format!("{:.*}", precision, n))),
+    Object::Quantity(n, dim) => Ok(Object::Str(format!("{:.*}{}", precision, n, dim))),
+    other => Err(RuntimeError::new(
+      ErrorKind::Type,
+      format!(
+        "Precision only applies to numbers, not {}",
+        other.type_name()
+      ),
+    )),
+  }
+}
+
+pub fn std_display(args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("display", &["solid"])?;
+  let solid = get_solid(args.get("solid")?)?;
   #[cfg(feature = "display")]
   display(solid);
-  Ok(Object::Number(0.0))
+  Ok(Object::None)
 }
 
-pub fn std_write_stl(args: Vec<Object>) -> Result<Object, RuntimeError> {
-  let solid = get_solid(args.get(0).unwrap().clone())?;
-  let name = get_str(args.get(1).unwrap().clone())?;
-  write_stl(&mut File::create(&name).unwrap(), solid, "test output").unwrap();
-  Ok(Object::Number(0.0))
+pub fn std_write_stl(outputs: &mut Outputs, args: Args) -> Result<Object, RuntimeError> {
+  let mut args = args.bind("write_stl", &["solid", "filename"])?;
+  let solid = get_solid(args.get("solid")?)?;
+  let name = get_str(args.get("filename")?)?;
+  outputs.export(&name, "stl", |mut file| {
+    write_stl(&mut file, solid, "test output")
+  })?;
+  Ok(Object::None)
 }
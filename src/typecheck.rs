@@ -0,0 +1,722 @@
+use diagnostic::{Diagnostic, Label};
+use parser::ast::{Argument, Expr, Meta, Operator, Param, Program, Span, Stmt, Type};
+use runtime::STD_LIB_FUNCTIONS;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A value whose type doesn't match an annotation or a stdlib signature
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+  pub msg: String,
+  pub span: Span,
+}
+
+impl TypeError {
+  /// Renders the error along with the source line it points at
+  pub fn render(&self, file: &str, program_string: &str) -> String {
+    Diagnostic {
+      severity: "error",
+      msg: self.msg.clone(),
+      primary: Some(Label {
+        file: file,
+        source: program_string,
+        span: self.span,
+        msg: String::new(),
+      }),
+      secondary: vec![],
+    }
+    .to_string()
+  }
+}
+
+/// Parameter names and types of a function, along with its return type
+#[derive(Debug, Clone, PartialEq)]
+struct Signature {
+  params: Vec<(String, Type)>,
+  /// Parameters that can be left out of a call
+  optional: Vec<String>,
+  ret: Type,
+}
+
+/// What is known about a variable before the program runs
+#[derive(Debug, Clone, PartialEq)]
+struct Binding {
+  ty: Type,
+  /// Set for functions whose parameters are known
+  signature: Option<Signature>,
+}
+
+/// Checks a program against the type annotations on its functions and the
+/// signatures of stdlib functions without running it. Types that can't be
+/// worked out statically are treated as `Object` and never reported.
+///
+/// Statements and expressions nested more than `max_nesting` deep are
+/// reported instead of checked, so that checking can't overflow a stack
+/// sized for `Limits::max_nesting`. `nesting` is how deeply the program is
+/// nested to begin with, e.g. for a module imported by a running program.
+pub fn check_program(
+  program: &Program,
+  nesting: usize,
+  max_nesting: Option<usize>,
+) -> Vec<TypeError> {
+  let mut checker = Checker {
+    scopes: vec![builtins()],
+    return_types: vec![],
+    errors: vec![],
+    nesting: nesting,
+    max_nesting: max_nesting,
+    too_deep: false,
+  };
+  for stmt in program {
+    checker.check_stmt(stmt);
+  }
+  checker.errors
+}
+
+fn signature(params: &[(&str, Type)], ret: Type) -> Signature {
+  Signature {
+    params: params
+      .iter()
+      .map(|(name, ty)| (name.to_string(), ty.clone()))
+      .collect(),
+    optional: vec![],
+    ret: ret,
+  }
+}
+
+/// Signatures of the stdlib functions. These must match the parameter names
+/// the functions in stdlib.rs bind their arguments to.
+fn stdlib_signature(name: &str) -> Option<Signature> {
+  let xyz = [
+    ("x", Type::Number),
+    ("y", Type::Number),
+    ("z", Type::Number),
+  ];
+  let two_vectors = [("a", Type::Vector), ("b", Type::Vector)];
+  Some(match name {
+    "print" => signature(&[("value", Type::Any)], Type::None),
+    "Box" => signature(
+      &[
+        ("length", Type::Number),
+        ("width", Type::Number),
+        ("height", Type::Number),
+      ],
+      Type::Solid,
+    ),
+    "Plane" => signature(&xyz, Type::Plane),
+    "difference" => signature(
+      &[("target", Type::Solid), ("tool", Type::Solid)],
+      Type::Solid,
+    ),
+    "rotate_x" => signature(
+      &[("solid", Type::Solid), ("angle", Type::Number)],
+      Type::Solid,
+    ),
+    "translate" => signature(
+      &[
+        ("solid", Type::Solid),
+        ("x", Type::Number),
+        ("y", Type::Number),
+        ("z", Type::Number),
+      ],
+      Type::Solid,
+    ),
+    "Vector" => signature(&xyz, Type::Vector),
+    "Point" => signature(&xyz, Type::Point),
+    "dot" => signature(&two_vectors, Type::Number),
+    "cross" => signature(&two_vectors, Type::Vector),
+    "normalize" => signature(&[("vector", Type::Vector)], Type::Vector),
+    "length" => signature(&[("vector", Type::Vector)], Type::Number),
+    "display" => signature(&[("solid", Type::Solid)], Type::None),
+    "write_stl" => signature(
+      &[("solid", Type::Solid), ("filename", Type::Str)],
+      Type::None,
+    ),
+    "len" => signature(&[("value", Type::Any)], Type::Number),
+    "str" => Signature {
+      optional: vec!["precision".to_string()],
+      ..signature(
+        &[("value", Type::Any), ("precision", Type::Number)],
+        Type::Str,
+      )
+    },
+    _ => return None,
+  })
+}
+
+/// The scope every program starts with, mirroring `Runtime::add_stdlib`
+fn builtins() -> HashMap<String, Binding> {
+  let mut scope = HashMap::new();
+  for name in STD_LIB_FUNCTIONS.iter() {
+    scope.insert(
+      name.to_string(),
+      Binding {
+        ty: Type::Function,
+        signature: stdlib_signature(name),
+      },
+    );
+  }
+  let constants = [
+    ("none", Type::None),
+    ("origin", Type::Point),
+    ("X", Type::Vector),
+    ("Y", Type::Vector),
+    ("Z", Type::Vector),
+  ];
+  for (name, ty) in constants.iter() {
+    scope.insert(
+      name.to_string(),
+      Binding {
+        ty: ty.clone(),
+        signature: None,
+      },
+    );
+  }
+  scope
+}
+
+struct Checker {
+  scopes: Vec<HashMap<String, Binding>>,
+  /// Declared return types of the functions being checked, innermost last
+  return_types: Vec<Option<Type>>,
+  errors: Vec<TypeError>,
+  /// Number of statements and expressions being checked, each inside the
+  /// last
+  nesting: usize,
+  max_nesting: Option<usize>,
+  /// Set once the nesting limit has been reported
+  too_deep: bool,
+}
+
+impl Checker {
+  fn error(&mut self, msg: String, span: Span) {
+    self.errors.push(TypeError {
+      msg: msg,
+      span: span,
+    });
+  }
+
+  fn lookup(&self, name: &str) -> Option<&Binding> {
+    self
+      .scopes
+      .iter()
+      .rev()
+      .filter_map(|scope| scope.get(name))
+      .next()
+  }
+
+  fn declare(&mut self, name: &str, binding: Binding) {
+    if let Some(scope) = self.scopes.last_mut() {
+      scope.insert(name.to_string(), binding);
+    }
+  }
+
+  /// Records an assignment. A variable that is assigned values of
+  /// different types is treated as `Object` from then on.
+  fn assign(&mut self, name: &str, ty: Type) {
+    for scope in self.scopes.iter_mut().rev() {
+      if let Some(binding) = scope.get_mut(name) {
+        if binding.ty != ty {
+          binding.ty = Type::Any;
+        }
+        binding.signature = None;
+        return;
+      }
+    }
+    self.declare(
+      name,
+      Binding {
+        ty: ty,
+        signature: None,
+      },
+    );
+  }
+
+  fn check_block(&mut self, stmts: &[Meta<Stmt>]) {
+    self.scopes.push(HashMap::new());
+    for stmt in stmts {
+      self.check_stmt(stmt);
+    }
+    self.scopes.pop();
+  }
+
+  /// Goes one level deeper into the program, reporting an error instead if
+  /// that is deeper than the nesting limit allows. Each successful call must
+  /// be matched by decrementing `nesting` on the way out.
+  fn enter(&mut self, span: Span) -> bool {
+    if let Some(max) = self.max_nesting {
+      if self.nesting >= max {
+        // one report is enough, however many branches go too deep
+        if !self.too_deep {
+          self.too_deep = true;
+          self.error(format!("Maximum nesting depth of {} exceeded", max), span);
+        }
+        return false;
+      }
+    }
+    self.nesting += 1;
+    true
+  }
+
+  fn check_stmt(&mut self, stmt: &Meta<Stmt>) {
+    if self.enter(stmt.span()) {
+      self.dispatch_stmt(stmt);
+      self.nesting -= 1;
+    }
+  }
+
+  fn dispatch_stmt(&mut self, stmt: &Meta<Stmt>) {
+    match stmt.inside {
+      Stmt::Block(ref stmts) => self.check_block(stmts),
+      Stmt::If(ref cond, ref body, ref else_body) => {
+        self.check_condition(cond);
+        self.check_stmt(body);
+        if let Some(else_body) = else_body {
+          self.check_stmt(else_body);
+        }
+      }
+      Stmt::For(ref assign, ref cond, ref inc, ref body) => {
+        self.check_stmt(assign);
+        self.check_condition(cond);
+        self.check_stmt(body);
+        self.check_stmt(inc);
+      }
+      Stmt::While(ref cond, ref body) => {
+        self.check_condition(cond);
+        self.check_stmt(body);
+      }
+      Stmt::ForIn(ref name, ref iterable, ref body) => {
+        let element = match self.check_expr(iterable) {
+          Type::List(element) => *element,
+          _ => Type::Any,
+        };
+        let mut scope = HashMap::new();
+        scope.insert(
+          name.clone(),
+          Binding {
+            ty: element,
+            signature: None,
+          },
+        );
+        self.scopes.push(scope);
+        self.check_stmt(body);
+        self.scopes.pop();
+      }
+      Stmt::Break | Stmt::Continue | Stmt::Error => (),
+      Stmt::Return(ref expr) => {
+        let found = match expr {
+          Some(expr) => self.check_expr(expr),
+          None => Type::None,
+        };
+        if let Some(Some(expected)) = self.return_types.last().cloned() {
+          if !expected.accepts(&found) {
+            self.error(
+              format!("Function should return {}, found {}", expected, found),
+              stmt.span(),
+            );
+          }
+        }
+      }
+      Stmt::Expr(ref expr) => {
+        self.check_expr(expr);
+      }
+      Stmt::Function(ref name, ref params, ref ret, ref body) => {
+        let signature = function_signature(params, ret);
+        self.declare(
+          name,
+          Binding {
+            ty: Type::Function,
+            signature: Some(signature),
+          },
+        );
+        self.check_function(params, ret, body, stmt.span());
+      }
+      Stmt::Assign(ref name, ref expr) => {
+        let ty = self.check_expr(expr);
+        self.assign(name, ty);
+      }
+      Stmt::Destructure(ref names, ref expr) => {
+        let element = match self.check_expr(expr) {
+          Type::List(element) => *element,
+          _ => Type::Any,
+        };
+        for name in names {
+          self.assign(name, element.clone());
+        }
+      }
+      Stmt::Import(ref path, ref alias) => {
+        let name = alias.clone().unwrap_or_else(|| {
+          let stem = Path::new(path).file_stem().and_then(|stem| stem.to_str());
+          stem.unwrap_or("").to_string()
+        });
+        self.assign(&name, Type::Map);
+      }
+    }
+  }
+
+  /// Checks the body of a function in a scope holding its parameters. Like
+  /// at runtime, each default value can see the parameters before it.
+  fn check_function(
+    &mut self,
+    params: &[Param],
+    ret: &Option<Type>,
+    body: &Meta<Stmt>,
+    span: Span,
+  ) {
+    self.scopes.push(HashMap::new());
+    for param in params {
+      let ty = param.ty.clone().unwrap_or(Type::Any);
+      if let Some(ref default) = param.default {
+        let found = self.check_expr(default);
+        if !ty.accepts(&found) {
+          self.error(
+            format!(
+              "Default value of parameter `{}` should be {}, found {}",
+              param.name, ty, found
+            ),
+            default.span(),
+          );
+        }
+      }
+      if let Some(scope) = self.scopes.last_mut() {
+        scope.insert(
+          param.name.clone(),
+          Binding {
+            ty: ty,
+            signature: None,
+          },
+        );
+      }
+    }
+    self.return_types.push(ret.clone());
+    self.check_stmt(body);
+    self.return_types.pop();
+    self.scopes.pop();
+    // falling off the end returns none
+    if let Some(ty) = ret {
+      if !ty.accepts(&Type::None) && !always_returns(body) {
+        self.error(
+          format!(
+            "Function should return {}, but can reach its end without returning",
+            ty
+          ),
+          span,
+        );
+      }
+    }
+  }
+
+  fn check_condition(&mut self, cond: &Meta<Expr>) {
+    let found = self.check_expr(cond);
+    if !Type::Bool.accepts(&found) {
+      self.error(
+        format!("Condition should be Bool, found {}", found),
+        cond.span(),
+      );
+    }
+  }
+
+  /// Works out the type of an expression, checking any calls within it
+  fn check_expr(&mut self, expr: &Meta<Expr>) -> Type {
+    if !self.enter(expr.span()) {
+      return Type::Any;
+    }
+    let ty = self.dispatch_expr(expr);
+    self.nesting -= 1;
+    ty
+  }
+
+  fn dispatch_expr(&mut self, expr: &Meta<Expr>) -> Type {
+    match expr.inside {
+      Expr::Number(_) | Expr::Quantity(..) => Type::Number,
+      Expr::Vector(..) => Type::Vector,
+      Expr::Bool(_) => Type::Bool,
+      Expr::Str(_) => Type::Str,
+      Expr::Interpolate(ref parts) => {
+        for part in parts {
+          self.check_expr(part);
+        }
+        Type::Str
+      }
+      Expr::Identifier(ref name) => self.lookup(name).map_or(Type::Any, |b| b.ty.clone()),
+      Expr::Binary(ref op, ref e1, ref e2) => {
+        let t1 = self.check_expr(e1);
+        let t2 = self.check_expr(e2);
+        binary_type(op, t1, t2)
+      }
+      Expr::Unary(Operator::Not, ref e) => {
+        self.check_expr(e);
+        Type::Bool
+      }
+      Expr::Unary(_, ref e) => self.check_expr(e),
+      Expr::FunctionCall(ref name, ref args) => {
+        let signature = self.lookup(name).and_then(|b| b.signature.clone());
+        self.check_call(expr, name, signature, None, args)
+      }
+      Expr::MethodCall(ref receiver, ref name, ref args) => {
+        let receiver_type = self.check_expr(receiver);
+        if receiver_type == Type::Map || receiver_type == Type::Any {
+          // might be a function stored in a map, e.g. from a module
+          self.check_call(expr, name, None, None, args)
+        } else {
+          let signature = self.lookup(name).and_then(|b| b.signature.clone());
+          self.check_call(expr, name, signature, Some(receiver_type), args)
+        }
+      }
+      Expr::Function(ref params, ref ret, ref body) => {
+        self.check_function(params, ret, body, expr.span());
+        Type::Function
+      }
+      Expr::Member(ref object, _) => {
+        self.check_expr(object);
+        Type::Any
+      }
+      Expr::Index(ref object, ref index) => {
+        let object = self.check_expr(object);
+        self.check_expr(index);
+        match object {
+          Type::List(element) => *element,
+          Type::Str => Type::Str,
+          _ => Type::Any,
+        }
+      }
+      Expr::Slice(ref object, ref start, ref end) => {
+        let object = self.check_expr(object);
+        for bound in start.iter().chain(end.iter()) {
+          self.check_expr(bound);
+        }
+        object
+      }
+      Expr::List(ref items) => {
+        let types: Vec<Type> = items.iter().map(|item| self.check_expr(item)).collect();
+        let element = match types.split_first() {
+          Some((first, rest)) if rest.iter().all(|ty| ty == first) => first.clone(),
+          _ => Type::Any,
+        };
+        Type::List(Box::new(element))
+      }
+      Expr::Map(ref entries) => {
+        for (_, value) in entries {
+          self.check_expr(value);
+        }
+        Type::Map
+      }
+      Expr::Range(ref start, ref end, ref step, _) => {
+        self.check_expr(start);
+        self.check_expr(end);
+        if let Some(step) = step {
+          self.check_expr(step);
+        }
+        Type::List(Box::new(Type::Number))
+      }
+    }
+  }
+
+  /// Checks the arguments of a call against the function's signature, if
+  /// it is known, and returns the type of the result. The receiver of a
+  /// method call is the first argument.
+  fn check_call(
+    &mut self,
+    expr: &Meta<Expr>,
+    name: &str,
+    signature: Option<Signature>,
+    receiver: Option<Type>,
+    args: &[Argument],
+  ) -> Type {
+    let mut positional: Vec<(Type, Span)> =
+      receiver.into_iter().map(|ty| (ty, expr.span())).collect();
+    let mut named = vec![];
+    for arg in args {
+      match arg {
+        Argument::Positional(e) => positional.push((self.check_expr(e), e.span())),
+        Argument::Named(arg_name, e) => named.push((arg_name, self.check_expr(e), e.span())),
+      }
+    }
+    let signature = match signature {
+      Some(signature) => signature,
+      None => return Type::Any,
+    };
+
+    if positional.len() > signature.params.len() {
+      self.error(
+        format!(
+          "`{}` takes {} arguments but was given {}",
+          name,
+          signature.params.len(),
+          positional.len()
+        ),
+        expr.span(),
+      );
+    }
+    let given = positional
+      .into_iter()
+      .zip(signature.params.iter())
+      .map(|((ty, span), (param, expected))| (param.clone(), expected.clone(), ty, span))
+      .collect::<Vec<_>>();
+    let mut checks = given;
+    for (arg_name, ty, span) in named {
+      match signature.params.iter().find(|(param, _)| param == arg_name) {
+        Some((param, expected)) => checks.push((param.clone(), expected.clone(), ty, span)),
+        None => self.error(
+          format!("`{}` has no parameter named `{}`", name, arg_name),
+          span,
+        ),
+      }
+    }
+    let missing = signature.params.iter().find(|(param, _)| {
+      !signature.optional.contains(param) && !checks.iter().any(|check| &check.0 == param)
+    });
+    if let Some((param, _)) = missing {
+      self.error(
+        format!("`{}` is missing argument `{}`", name, param),
+        expr.span(),
+      );
+    }
+    for (param, expected, found, span) in checks {
+      if !expected.accepts(&found) {
+        self.error(
+          format!(
+            "Argument `{}` of `{}` should be {}, found {}",
+            param, name, expected, found
+          ),
+          span,
+        );
+      }
+    }
+    signature.ret
+  }
+}
+
+fn function_signature(params: &[Param], ret: &Option<Type>) -> Signature {
+  Signature {
+    params: params
+      .iter()
+      .map(|param| (param.name.clone(), param.ty.clone().unwrap_or(Type::Any)))
+      .collect(),
+    optional: params
+      .iter()
+      .filter(|param| param.default.is_some())
+      .map(|param| param.name.clone())
+      .collect(),
+    ret: ret.clone().unwrap_or(Type::Any),
+  }
+}
+
+/// Whether every way through a function body ends in a `return`
+fn always_returns(stmt: &Meta<Stmt>) -> bool {
+  match stmt.inside {
+    Stmt::Return(_) => true,
+    Stmt::Block(ref stmts) => stmts.iter().any(always_returns),
+    Stmt::If(_, ref body, Some(ref else_body)) => always_returns(body) && always_returns(else_body),
+    _ => false,
+  }
+}
+
+/// Type of the result of a binary operator, where it can be known
+fn binary_type(op: &Operator, t1: Type, t2: Type) -> Type {
+  match op {
+    Operator::Equal
+    | Operator::NotEqual
+    | Operator::Less
+    | Operator::LessEqual
+    | Operator::Greater
+    | Operator::GreaterEqual
+    | Operator::And
+    | Operator::Or
+    | Operator::Not => Type::Bool,
+    Operator::Union | Operator::Intersection => match (t1, t2) {
+      (Type::Solid, Type::Solid) => Type::Solid,
+      _ => Type::Any,
+    },
+    Operator::Add if t1 == Type::Str && t2 == Type::Str => Type::Str,
+    Operator::Subtract if t1 == Type::Point && t2 == Type::Point => Type::Vector,
+    _ => match (t1, t2) {
+      (Type::Number, Type::Number) => Type::Number,
+      (Type::Vector, Type::Vector)
+      | (Type::Vector, Type::Number)
+      | (Type::Number, Type::Vector) => Type::Vector,
+      (Type::Point, Type::Vector) | (Type::Vector, Type::Point) => Type::Point,
+      _ => Type::Any,
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parser::parse::parse_program;
+
+  fn check(source: &str) -> Vec<String> {
+    let program = parse_program(source).expect("program should parse");
+    check_program(&program, 0, None)
+      .into_iter()
+      .map(|error| error.msg)
+      .collect()
+  }
+
+  #[test]
+  fn test_annotations() {
+    let program = "
+      fn cube(size: Number) -> Solid { return Box(size, size, size); }
+      fn label(solid: Solid, names: List<Str> = [\"a\", \"b\"]) -> Str { return names[0]; }
+      c = cube(10mm);
+      print(label(c));
+      print(difference(c, cube(size = 5)));
+    ";
+    assert_eq!(check(program), Vec::<String>::new());
+    assert_eq!(
+      check("fn f(n: Number) -> Str { return n; } f(\"x\"); Box(1, 2, \"3\");"),
+      vec![
+        "Function should return Str, found Number",
+        "Argument `n` of `f` should be Number, found Str",
+        "Argument `height` of `Box` should be Number, found Str",
+      ]
+    );
+    assert_eq!(
+      check("x = 1; x = \"one\"; rotate_x(x, 1); if (1) { } translate(Box(1, 1, 1), 1, 2, 3, 4);"),
+      vec![
+        "Condition should be Bool, found Number",
+        "`translate` takes 4 arguments but was given 5",
+      ]
+    );
+    assert_eq!(
+      check("fn f(a, b = 1) { } f(); f(1); f(b = 2); Box(1, 2); str(1);"),
+      vec![
+        "`f` is missing argument `a`",
+        "`f` is missing argument `a`",
+        "`Box` is missing argument `height`",
+      ]
+    );
+    assert_eq!(
+      check(
+        "fn f(n) -> Number { if (n) { return 1; } }
+         fn g(n) -> Number { if (n) { return 1; } else { return 2; } }
+         fn h(n) -> None { }
+         k = fn() -> Str { print(1); };"
+      ),
+      vec![
+        "Function should return Number, but can reach its end without returning",
+        "Function should return Str, but can reach its end without returning",
+      ]
+    );
+    // defaults see the earlier parameters, not outer variables
+    assert_eq!(
+      check("w = 1; fn f(w: Str, s: Str = w) { }"),
+      Vec::<String>::new()
+    );
+  }
+
+  #[test]
+  fn test_nesting_limit() {
+    let source = format!("x = {};", vec!["1"; 50].join(" + "));
+    let program = parse_program(&source).expect("program should parse");
+    let errors: Vec<String> = check_program(&program, 0, Some(20))
+      .into_iter()
+      .map(|error| error.msg)
+      .collect();
+    // reported once, even though many branches go too deep
+    assert_eq!(errors, vec!["Maximum nesting depth of 20 exceeded"]);
+    assert_eq!(check_program(&program, 0, Some(100)), vec![]);
+    assert_eq!(check_program(&program, 90, Some(100)).len(), 1);
+  }
+}
@@ -0,0 +1,150 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+/// A unit suffix that can follow a numeric literal, e.g. `10mm` or `90deg`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Unit {
+  Millimeter,
+  Centimeter,
+  Meter,
+  Inch,
+  Degree,
+  Radian,
+  Turn,
+}
+
+impl Unit {
+  /// Looks up the unit for a literal suffix
+  pub fn from_suffix(suffix: &str) -> Option<Unit> {
+    match suffix {
+      "mm" => Some(Unit::Millimeter),
+      "cm" => Some(Unit::Centimeter),
+      "m" => Some(Unit::Meter),
+      "in" => Some(Unit::Inch),
+      "deg" => Some(Unit::Degree),
+      "rad" => Some(Unit::Radian),
+      "turn" => Some(Unit::Turn),
+      _ => None,
+    }
+  }
+
+  pub fn dimension(self) -> Dimension {
+    match self {
+      Unit::Millimeter | Unit::Centimeter | Unit::Meter | Unit::Inch => Dimension::LENGTH,
+      Unit::Degree | Unit::Radian | Unit::Turn => Dimension::ANGLE,
+    }
+  }
+
+  /// Converts a value in this unit into the base unit of its dimension:
+  /// millimeters for lengths and radians for angles
+  pub fn to_base(self, value: f64) -> f64 {
+    match self {
+      Unit::Millimeter | Unit::Radian => value,
+      Unit::Centimeter => value * 10.0,
+      Unit::Meter => value * 1000.0,
+      Unit::Inch => value * 25.4,
+      Unit::Degree => value * PI / 180.0,
+      Unit::Turn => value * 2.0 * PI,
+    }
+  }
+}
+
+/// An axis suffix that turns a numeric literal into a vector along that axis,
+/// e.g. `2X` or `10Y`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Axis {
+  X,
+  Y,
+  Z,
+}
+
+impl Axis {
+  /// Looks up the axis for a literal suffix
+  pub fn from_suffix(suffix: &str) -> Option<Axis> {
+    match suffix {
+      "X" => Some(Axis::X),
+      "Y" => Some(Axis::Y),
+      "Z" => Some(Axis::Z),
+      _ => None,
+    }
+  }
+
+  /// Components of a vector of the given length along this axis
+  pub fn components(self, length: f64) -> [f64; 3] {
+    match self {
+      Axis::X => [length, 0.0, 0.0],
+      Axis::Y => [0.0, length, 0.0],
+      Axis::Z => [0.0, 0.0, length],
+    }
+  }
+}
+
+/// The dimension of a quantity as powers of length and angle. A value with
+/// no dimension is a plain number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dimension {
+  pub length: i32,
+  pub angle: i32,
+}
+
+impl Dimension {
+  pub const NONE: Dimension = Dimension {
+    length: 0,
+    angle: 0,
+  };
+  pub const LENGTH: Dimension = Dimension {
+    length: 1,
+    angle: 0,
+  };
+  pub const ANGLE: Dimension = Dimension {
+    length: 0,
+    angle: 1,
+  };
+
+  pub fn is_none(self) -> bool {
+    self == Dimension::NONE
+  }
+
+  /// Dimension of the product of two quantities
+  pub fn mul(self, other: Dimension) -> Dimension {
+    Dimension {
+      length: self.length + other.length,
+      angle: self.angle + other.angle,
+    }
+  }
+
+  /// Dimension of the quotient of two quantities
+  pub fn div(self, other: Dimension) -> Dimension {
+    Dimension {
+      length: self.length - other.length,
+      angle: self.angle - other.angle,
+    }
+  }
+}
+
+impl fmt::Display for Dimension {
+  /// Formats the dimension in base units, e.g. `mm^2/rad`
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut numerator = vec![];
+    let mut denominator = vec![];
+    for &(unit, power) in [("mm", self.length), ("rad", self.angle)].iter() {
+      let part = match power.abs() {
+        1 => unit.to_string(),
+        p => format!("{}^{}", unit, p),
+      };
+      if power > 0 {
+        numerator.push(part);
+      } else if power < 0 {
+        denominator.push(part);
+      }
+    }
+    if numerator.is_empty() {
+      numerator.push("1".to_string());
+    }
+    write!(f, "{}", numerator.join("*"))?;
+    if !denominator.is_empty() {
+      write!(f, "/{}", denominator.join("*"))?;
+    }
+    Ok(())
+  }
+}